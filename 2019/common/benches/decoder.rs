@@ -0,0 +1,61 @@
+use std::path::PathBuf;
+use criterion::{criterion_group, criterion_main, Criterion, black_box};
+use common::computer::{read_program_file, Computer, OpCode, State};
+use common::disasm::decode;
+
+fn boost() -> Vec<i64> {
+    read_program_file(PathBuf::from("../day09/assets/boost.txt")).unwrap()
+}
+
+/// Addresses and raw instructions executed by BOOST in test mode, in order
+fn executed_instructions() -> Vec<(i64, i64)> {
+    let mut computer = Computer::new(boost());
+    computer.add_input(1);
+    let mut instructions = vec![];
+    while computer.state != State::Stopped {
+        let event = computer.step().unwrap();
+        instructions.push((event.instruction_pointer, event.raw_opcode));
+    }
+    instructions
+}
+
+fn run_boost(input: i64, cache: bool) -> i64 {
+    let mut computer = Computer::new(boost());
+    if cache {
+        computer.enable_decode_cache();
+    }
+    computer.add_input(input);
+    computer.run().unwrap();
+    computer.last_output().unwrap()
+}
+
+fn decoders(c: &mut Criterion) {
+    let program = boost();
+    let instructions = executed_instructions();
+    let mut group = c.benchmark_group("decode BOOST instructions");
+    group.bench_function("opcode and modes", |b| b.iter(|| {
+        for (address, instruction) in instructions.iter() {
+            black_box(OpCode::decode(*instruction, *address).unwrap());
+        }
+    }));
+    // Baseline: the disassembler decodes the same instructions from the loaded program and fetches their parameters
+    group.bench_function("disassembled line", |b| b.iter(|| {
+        for (address, _) in instructions.iter() {
+            black_box(decode(|address| program.get(address as usize).copied(), *address));
+        }
+    }));
+    group.finish();
+}
+
+fn boost_runs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("run BOOST");
+    group.sample_size(10);
+    group.bench_function("test mode", |b| b.iter(|| run_boost(black_box(1), false)));
+    group.bench_function("test mode, decode cache", |b| b.iter(|| run_boost(black_box(1), true)));
+    group.bench_function("sensor boost", |b| b.iter(|| run_boost(black_box(2), false)));
+    group.bench_function("sensor boost, decode cache", |b| b.iter(|| run_boost(black_box(2), true)));
+    group.finish();
+}
+
+criterion_group!(benches, decoders, boost_runs);
+criterion_main!(benches);
@@ -0,0 +1,180 @@
+//!
+//! Text exchanges with the programs speaking ASCII: maps, springscript, adventure commands...
+//!
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use crate::computer::{Computer, IntcodeError, State};
+
+/// Piece of output: text, or a value too large to be an ASCII character
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AsciiOutput {
+    Text(String),
+    Value(i64)
+}
+
+/// Groups the characters of the output, values outside `0..=127` being kept as numbers
+pub fn decode_output(values: &[i64]) -> Vec<AsciiOutput> {
+    let mut decoded = vec![];
+    let mut text = String::new();
+    for value in values {
+        match value {
+            0..=127 => text.push(*value as u8 as char),
+            _ => {
+                if !text.is_empty() {
+                    decoded.push(AsciiOutput::Text(std::mem::take(&mut text)));
+                }
+                decoded.push(AsciiOutput::Value(*value));
+            }
+        }
+    }
+    if !text.is_empty() {
+        decoded.push(AsciiOutput::Text(text));
+    }
+    decoded
+}
+
+/// Character of a line which doesn't fit in `0..=127`, the values `decode_output` reads as text
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct NonAsciiError(pub char);
+
+impl fmt::Display for NonAsciiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?} isn't an ASCII character", self.0)
+    }
+}
+
+impl Error for NonAsciiError {}
+
+///
+/// Computer taking lines of text as input and giving text back
+///
+#[derive(Debug)]
+pub struct AsciiComputer {
+    pub computer: Computer
+}
+
+impl AsciiComputer {
+    pub fn new(program: Vec<i64>) -> AsciiComputer {
+        AsciiComputer { computer: Computer::new(program) }
+    }
+
+    /// Queues the characters of the line followed by a newline, nothing is queued if one of them isn't ASCII
+    pub fn send_line(&mut self, line: &str) -> Result<(), NonAsciiError> {
+        if let Some(character) = line.chars().find(|character| !character.is_ascii()) {
+            return Err(NonAsciiError(character))
+        }
+        for character in line.chars().chain(std::iter::once('\n')) {
+            self.computer.add_input(character as i64);
+        }
+        Ok(())
+    }
+
+    pub fn run(&mut self) -> Result<State, IntcodeError> {
+        self.computer.run()
+    }
+
+    /// Drains the outputs produced so far
+    pub fn read_output(&mut self) -> Vec<AsciiOutput> {
+        decode_output(&self.computer.drain_outputs())
+    }
+
+    /// Drains the outputs as text, a large value being written as a number on its own line
+    pub fn read_text(&mut self) -> String {
+        self.read_output().into_iter().map(|output| match output {
+            AsciiOutput::Text(text) => text,
+            AsciiOutput::Value(value) => format!("{}\n", value)
+        }).collect()
+    }
+
+    ///
+    /// Runs the program, writing its text to `output` and feeding it the lines read from `input`
+    /// each time it waits for some. Ends when the program stops or `input` is exhausted.
+    /// A line with a character which isn't ASCII is reported on `output` and dropped.
+    ///
+    pub fn interact<R: BufRead, W: Write>(&mut self, mut input: R, mut output: W) -> Result<State, Box<dyn Error>> {
+        loop {
+            let state = self.run()?;
+            output.write_all(self.read_text().as_bytes())?;
+            output.flush()?;
+
+            match state {
+                State::AwaitingInput => {
+                    let mut line = String::new();
+                    if input.read_line(&mut line)? == 0 {
+                        return Ok(state);
+                    }
+                    if let Err(error) = self.send_line(line.trim_end_matches(['\r', '\n'])) {
+                        writeln!(output, "{}", error)?;
+                    }
+                },
+                State::Stopped | State::Interrupted => return Ok(state),
+                State::Started | State::Paused => ()
+            }
+        }
+    }
+
+    /// Terminal mode, the user playing the program from stdin and stdout
+    pub fn interactive(&mut self) -> Result<State, Box<dyn Error>> {
+        let stdin = io::stdin();
+        self.interact(stdin.lock(), io::stdout())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ascii::{AsciiComputer, AsciiOutput, NonAsciiError, decode_output};
+    use crate::asm::assemble;
+    use crate::computer::State;
+
+    /// Echoes every line it reads
+    const ECHO: &str = "
+        loop:   in [char]
+                out [char]
+                jt #1, #loop
+        char:   data 0";
+
+    #[test]
+    fn test_decode_output() {
+        assert_eq!(decode_output(&[35, 46, 10, 1234, 10, -1]), vec![
+            AsciiOutput::Text("#.\n".to_string()),
+            AsciiOutput::Value(1234),
+            AsciiOutput::Text("\n".to_string()),
+            AsciiOutput::Value(-1)
+        ]);
+        assert!(decode_output(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_lines_and_text() {
+        let program = assemble("
+                in [char]
+                out [char]
+                out #19349
+                hlt
+        char:   data 0").unwrap();
+        let mut computer = AsciiComputer::new(program);
+        computer.send_line("").unwrap();
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.read_output(), vec![AsciiOutput::Text("\n".to_string()), AsciiOutput::Value(19349)]);
+
+        let mut computer = AsciiComputer::new(assemble(ECHO).unwrap());
+        computer.send_line("WALK").unwrap();
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+        assert_eq!(computer.read_text(), "WALK\n");
+        assert_eq!(computer.read_text(), "");
+
+        assert_eq!(computer.send_line("RUN \u{e9}"), Err(NonAsciiError('\u{e9}')));
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+        assert_eq!(computer.read_text(), "");
+    }
+
+    #[test]
+    fn test_interact() {
+        let mut computer = AsciiComputer::new(assemble(ECHO).unwrap());
+        let mut output = vec![];
+        let state = computer.interact("north\r\ntake caf\u{e9}\ntake mug\n".as_bytes(), &mut output).unwrap();
+        assert_eq!(state, State::AwaitingInput);
+        assert_eq!(String::from_utf8(output).unwrap(), "north\n'\u{e9}' isn't an ASCII character\ntake mug\n");
+    }
+}
@@ -0,0 +1,344 @@
+//! Intcode assembler
+//!
+//! One statement per line, `;` starts a comment:
+//!
+//! ```text
+//!         in [value]             ; store the input
+//! loop:   jf [value], #end
+//!         add [value], #-1, [value]
+//!         jt #1, #loop
+//! end:    out [rb+0]
+//!         hlt
+//! value:  data -1, 8
+//!         reserve 4
+//! ```
+//!
+//! Parameters are `[x]` (positional), `#x` (immediate) or `[rb+x]` (relative), where `x` is a
+//! number, a label or a label with an offset such as `value+1`.
+//! A line can also be prefixed by its address, `12:`, as printed by the disassembler,
+//! the assembler then checks that the statement really lands at this address.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use crate::computer::{OpCode, Mode};
+use crate::registry::InstructionSet;
+
+/// Cells a single `reserve` can add
+const RESERVE_LIMIT: usize = 1 << 24;
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum AsmErrorKind {
+    UnknownMnemonic(String),
+    InvalidOperand(String),
+    WrongOperandCount { expected: usize, found: usize },
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    AddressMismatch { expected: i64, found: i64 },
+    /// A label with an offset giving an address outside of the 64 bits range
+    AddressOverflow(String),
+    ReserveTooLarge(usize)
+}
+
+/// An assembly error and the 1-based line where it happened
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AsmErrorKind::UnknownMnemonic(mnemonic) => write!(f, "unknown mnemonic {}", mnemonic),
+            AsmErrorKind::InvalidOperand(operand) => write!(f, "invalid operand {}", operand),
+            AsmErrorKind::WrongOperandCount { expected, found } => {
+                write!(f, "expected {} operands, found {}", expected, found)
+            },
+            AsmErrorKind::UndefinedLabel(label) => write!(f, "undefined label {}", label),
+            AsmErrorKind::DuplicateLabel(label) => write!(f, "label {} is already defined", label),
+            AsmErrorKind::AddressMismatch { expected, found } => {
+                write!(f, "statement expected at address {} but lands at {}", expected, found)
+            },
+            AsmErrorKind::AddressOverflow(value) => write!(f, "{} doesn't fit in 64 bits", value),
+            AsmErrorKind::ReserveTooLarge(count) => {
+                write!(f, "can't reserve {} cells, at most {}", count, RESERVE_LIMIT)
+            }
+        }
+    }
+}
+
+impl Error for AsmError {}
+
+/// A value known at the first pass or a label resolved at the second one
+#[derive(Debug)]
+enum Value {
+    Number(i64),
+    Label(String, i64)
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction(OpCode, Vec<(Mode, Value)>),
+    Data(Vec<Value>),
+    Reserve(usize)
+}
+
+impl Statement {
+    fn cell_count(&self) -> usize {
+        match self {
+            Statement::Instruction(_, parameters) => parameters.len() + 1,
+            Statement::Data(values) => values.len(),
+            Statement::Reserve(count) => *count
+        }
+    }
+}
+
+fn is_label(string: &str) -> bool {
+    let mut chars = string.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {},
+        _ => return false
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && string != "rb"
+}
+
+/// Parses `12`, `-3`, `label`, `label+2` or `label-1`
+fn parse_value(string: &str) -> Option<Value> {
+    let string = string.trim();
+    if let Ok(number) = string.parse::<i64>() {
+        return Some(Value::Number(number))
+    }
+    let (label, offset) = match string.find(['+', '-']) {
+        Some(index) => (string[..index].trim(), string[index..].replace(' ', "").trim_start_matches('+').parse::<i64>().ok()?),
+        None => (string, 0)
+    };
+    if is_label(label) {
+        Some(Value::Label(label.to_string(), offset))
+    } else {
+        None
+    }
+}
+
+fn parse_parameter(string: &str) -> Option<(Mode, Value)> {
+    let string = string.trim();
+    if let Some(value) = string.strip_prefix('#') {
+        return parse_value(value).map(|value| (Mode::Immediate, value))
+    }
+    let inner = string.strip_prefix('[')?.strip_suffix(']')?.trim();
+    match inner.strip_prefix("rb") {
+        Some(offset) if offset.trim_start().starts_with(['+', '-']) => {
+            let offset = offset.replace(' ', "");
+            parse_value(offset.trim_start_matches('+')).map(|value| (Mode::Relative, value))
+        },
+        _ => parse_value(inner).map(|value| (Mode::Positional, value))
+    }
+}
+
+fn parse_statement(text: &str, line: usize, instructions: &InstructionSet) -> Result<Statement, AsmError> {
+    let error = |kind| AsmError { line, kind };
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(index) => (&text[..index], text[index..].trim()),
+        None => (text, "")
+    };
+    let operands: Vec<&str> = if rest.is_empty() {
+        vec![]
+    } else {
+        rest.split(',').map(|x| x.trim()).collect()
+    };
+
+    match mnemonic {
+        "data" => {
+            operands
+                .iter()
+                .map(|x| parse_value(x).ok_or_else(|| error(AsmErrorKind::InvalidOperand(x.to_string()))))
+                .collect::<Result<Vec<Value>, AsmError>>()
+                .map(Statement::Data)
+        },
+        "reserve" => {
+            if operands.len() != 1 {
+                return Err(error(AsmErrorKind::WrongOperandCount { expected: 1, found: operands.len() }))
+            }
+            match operands[0].parse::<usize>() {
+                Ok(count) if count > RESERVE_LIMIT => Err(error(AsmErrorKind::ReserveTooLarge(count))),
+                Ok(count) => Ok(Statement::Reserve(count)),
+                Err(_) => Err(error(AsmErrorKind::InvalidOperand(operands[0].to_string())))
+            }
+        },
+        _ => {
+            let definition = match instructions.definition_by_mnemonic(mnemonic) {
+                Some(definition) => definition,
+                None => return Err(error(AsmErrorKind::UnknownMnemonic(mnemonic.to_string())))
+            };
+            let opcode = definition.opcode;
+            if operands.len() != definition.parameters.len() {
+                return Err(error(AsmErrorKind::WrongOperandCount { expected: definition.parameters.len(), found: operands.len() }))
+            }
+            operands
+                .iter()
+                .map(|x| parse_parameter(x).ok_or_else(|| error(AsmErrorKind::InvalidOperand(x.to_string()))))
+                .collect::<Result<Vec<(Mode, Value)>, AsmError>>()
+                .map(|parameters| Statement::Instruction(opcode, parameters))
+        }
+    }
+}
+
+fn resolve(value: &Value, labels: &HashMap<String, i64>, line: usize) -> Result<i64, AsmError> {
+    match value {
+        Value::Number(number) => Ok(*number),
+        Value::Label(label, offset) => match labels.get(label) {
+            Some(address) => address.checked_add(*offset).ok_or_else(|| AsmError {
+                line,
+                kind: AsmErrorKind::AddressOverflow(format!("{}{:+}", label, offset))
+            }),
+            None => Err(AsmError { line, kind: AsmErrorKind::UndefinedLabel(label.clone()) })
+        }
+    }
+}
+
+///
+/// Assembles the source into a program ready for `Computer::new`
+///
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    assemble_with(source, &InstructionSet::standard())
+}
+
+/// Assembles the source with the mnemonics of the set, see `registry`
+pub fn assemble_with(source: &str, instructions: &InstructionSet) -> Result<Vec<i64>, AsmError> {
+
+    // First pass: place every statement and label
+    let mut labels: HashMap<String, i64> = HashMap::new();
+    let mut statements: Vec<(usize, Statement)> = vec![];
+    let mut address: i64 = 0;
+
+    for (index, text) in source.lines().enumerate() {
+        let line = index + 1;
+        let mut text = match text.find(';') {
+            Some(index) => text[..index].trim(),
+            None => text.trim()
+        };
+
+        // Labels and address markers
+        while let Some(index) = text.find(':') {
+            let prefix = text[..index].trim();
+            if let Ok(expected) = prefix.parse::<i64>() {
+                if expected != address {
+                    return Err(AsmError { line, kind: AsmErrorKind::AddressMismatch { expected, found: address } })
+                }
+            } else if is_label(prefix) {
+                if labels.insert(prefix.to_string(), address).is_some() {
+                    return Err(AsmError { line, kind: AsmErrorKind::DuplicateLabel(prefix.to_string()) })
+                }
+            } else {
+                break
+            }
+            text = text[index + 1..].trim();
+        }
+
+        if text.is_empty() {
+            continue
+        }
+
+        let statement = parse_statement(text, line, instructions)?;
+        address += statement.cell_count() as i64;
+        statements.push((line, statement));
+    }
+
+    // Second pass: encode with the labels resolved
+    let mut program = vec![];
+    for (line, statement) in statements {
+        match statement {
+            Statement::Instruction(opcode, parameters) => {
+                let modes = parameters.iter().map(|(mode, _)| *mode).collect::<Vec<Mode>>();
+                program.push(opcode.encode(&modes));
+                for (_, value) in parameters.iter() {
+                    program.push(resolve(value, &labels, line)?);
+                }
+            },
+            Statement::Data(values) => {
+                for value in values.iter() {
+                    program.push(resolve(value, &labels, line)?);
+                }
+            },
+            Statement::Reserve(count) => program.extend(vec![0; count])
+        }
+    }
+
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{assemble, AsmError, AsmErrorKind};
+    use crate::disasm::listing;
+    use crate::computer::computer;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_assemble() {
+        let source = "
+                in [value]                      ; read
+                eq [value], [eight], [value]
+                out [value]
+                hlt
+        value:  data -1
+        eight:  data 8
+        ";
+        assert_eq!(assemble(source).unwrap(), vec![3,9,8,9,10,9,4,9,99,-1,8]);
+    }
+
+    #[test]
+    fn test_assemble_modes_and_directives() {
+        let source = "
+        start:  rb #3
+                add #4, #38, [rb+4]
+                jt #1, #end
+                reserve 2
+        end:    hlt
+                data start, end+1, -5";
+        assert_eq!(assemble(source).unwrap(), vec![109,3,21101,4,38,4,1105,1,11,0,0,99,0,12,-5]);
+    }
+
+    #[test]
+    fn test_assembled_program_runs() {
+        let source = "
+                in [counter]
+        loop:   out [counter]
+                add [counter], #-1, [counter]
+                jt [counter], #loop
+                hlt
+        counter: data 0";
+        let program = assemble(source).unwrap();
+        let (_, buffer) = computer(program, Some(VecDeque::from(vec![3]))).unwrap();
+        assert_eq!(buffer, vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_assemble_disassemble_round_trip() {
+        let programs = vec![
+            vec![3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9],
+            vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99],
+            vec![3,3,1105,-1,9,1101,0,0,12,4,12,99,1],
+            // mode digits of missing parameters and digits past the modes can't be written in assembly
+            vec![10104,7,1099,21101,1,2,0,1000001,0,0,0,99],
+        ];
+        for program in programs {
+            assert_eq!(assemble(&listing(&program)).unwrap(), program);
+        }
+    }
+
+    #[test]
+    fn test_assemble_errors() {
+        assert_eq!(assemble("hlt\nfoo [1]"), Err(AsmError { line: 2, kind: AsmErrorKind::UnknownMnemonic("foo".to_string()) }));
+        assert_eq!(assemble("add [1], [2]"), Err(AsmError { line: 1, kind: AsmErrorKind::WrongOperandCount { expected: 3, found: 2 } }));
+        assert_eq!(assemble("out {1}"), Err(AsmError { line: 1, kind: AsmErrorKind::InvalidOperand("{1}".to_string()) }));
+        assert_eq!(assemble("\n\njt #1, #nowhere"), Err(AsmError { line: 3, kind: AsmErrorKind::UndefinedLabel("nowhere".to_string()) }));
+        assert_eq!(assemble("a: hlt\na: hlt"), Err(AsmError { line: 2, kind: AsmErrorKind::DuplicateLabel("a".to_string()) }));
+        assert_eq!(assemble("0: hlt\n3: hlt"), Err(AsmError { line: 2, kind: AsmErrorKind::AddressMismatch { expected: 3, found: 1 } }));
+        let overflow = assemble("hlt\na: data a+9223372036854775807").unwrap_err();
+        assert_eq!(overflow, AsmError { line: 2, kind: AsmErrorKind::AddressOverflow("a+9223372036854775807".to_string()) });
+        assert_eq!(overflow.to_string(), "line 2: a+9223372036854775807 doesn't fit in 64 bits");
+        assert_eq!(assemble("reserve 18446744073709551615"), Err(AsmError { line: 1, kind: AsmErrorKind::ReserveTooLarge(usize::MAX) }));
+    }
+}
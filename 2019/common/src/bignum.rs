@@ -0,0 +1,297 @@
+//!
+//! Computer whose cells hold integers of any size, for the programs going past 64 bits.
+//!
+//! Only the data is unbounded: instructions, addresses, jump targets and the relative base must
+//! still fit in an `i64`, executing one that doesn't fails with `IntcodeError::Overflow`.
+//! Instructions are decoded and their parameters resolved by the code of `Computer`, so a program
+//! fitting in 64 bits runs the same way, and fails with the same errors, on both computers.
+//! It only knows the standard opcodes, the ones of an `InstructionSet` work on `i64` values.
+//!
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use num_bigint::BigInt;
+use crate::computer::{IntcodeError, Mode, OpCode, ResumeMode, State, parameter_cell, parameter_address};
+use crate::registry;
+
+#[derive(Debug, Clone)]
+pub struct BigComputer {
+    memory: HashMap<i64, BigInt>,
+    inputs: VecDeque<BigInt>,
+    outputs: Vec<BigInt>,
+    pub resume_mode: ResumeMode,
+    pub state: State,
+    instruction_cursor: i64,
+    base: i64
+}
+
+impl BigComputer {
+    pub fn new(program: Vec<BigInt>) -> BigComputer {
+        BigComputer {
+            memory: program.into_iter().enumerate().map(|(address, value)| (address as i64, value)).collect(),
+            inputs: VecDeque::new(),
+            outputs: vec![],
+            resume_mode: ResumeMode::Disable,
+            state: State::Started,
+            instruction_cursor: 0,
+            base: 0
+        }
+    }
+
+    /// Loads a program written with 64 bits values
+    pub fn from_program(program: &[i64]) -> BigComputer {
+        BigComputer::new(program.iter().map(|value| BigInt::from(*value)).collect())
+    }
+
+    pub fn set_resume_mode(&mut self, mode: ResumeMode) {
+        self.resume_mode = mode;
+    }
+
+    pub fn add_input<V: Into<BigInt>>(&mut self, input: V) {
+        self.inputs.push_back(input.into());
+    }
+
+    /// Copy of the cell, a cell never written reads as zero
+    pub fn peek(&self, address: i64) -> BigInt {
+        self.memory.get(&address).cloned().unwrap_or_default()
+    }
+
+    /// Replaces the cell, handing back what it held before or zero for a fresh cell
+    pub fn poke(&mut self, address: i64, value: BigInt) -> BigInt {
+        self.memory.insert(address, value).unwrap_or_default()
+    }
+
+    /// Values output since the last `drain_outputs`
+    pub fn outputs(&self) -> &[BigInt] {
+        &self.outputs
+    }
+
+    /// Moves the values output so far out of the computer
+    pub fn drain_outputs(&mut self) -> Vec<BigInt> {
+        std::mem::take(&mut self.outputs)
+    }
+
+    /// Where the next step reads its instruction, always a 64 bits address
+    pub fn instruction_pointer(&self) -> i64 {
+        self.instruction_cursor
+    }
+
+    /// Steps until a stop, an output in resume mode or an empty input queue, there is no limit to set
+    pub fn run(&mut self) -> Result<State, IntcodeError> {
+        loop {
+            self.step()?;
+            match self.state {
+                State::Paused | State::AwaitingInput | State::Stopped => return Ok(self.state),
+                _ => ()
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<(), IntcodeError> {
+        self.state = State::Started;
+        let instruction_pointer = self.instruction_cursor;
+
+        let instruction = self.memory.get(&instruction_pointer).ok_or(IntcodeError::OutOfMemory { instruction_pointer })?;
+        let opcode_raw = i64::try_from(instruction).map_err(|_| {
+            // no i64 holds the instruction, the five digits giving its opcode and modes are reported instead
+            let digits = i64::try_from(instruction % 100_000).unwrap();
+            IntcodeError::Overflow { instruction_pointer, opcode: digits }
+        })?;
+        let (parameter, opcode) = OpCode::decode(opcode_raw, instruction_pointer)?;
+        let decoded = Decoded { instruction_pointer, opcode_raw, modes: parameter.modes() };
+        let to_i64 = |value: BigInt| i64::try_from(value).map_err(|_| decoded.overflow());
+
+        let mut increment = OpCode::get_increment(opcode, registry::standard());
+        match opcode {
+            OpCode::Add | OpCode::Multiply | OpCode::LessThan | OpCode::Equals => {
+                let operand_1 = self.operand(&decoded, 1)?;
+                let operand_2 = self.operand(&decoded, 2)?;
+                let result = match opcode {
+                    OpCode::Add => operand_1 + operand_2,
+                    OpCode::Multiply => operand_1 * operand_2,
+                    OpCode::LessThan => BigInt::from((operand_1 < operand_2) as i64),
+                    _ => BigInt::from((operand_1 == operand_2) as i64)
+                };
+                let result_address = self.address(&decoded, 3)?;
+                self.memory.insert(result_address, result);
+            },
+            OpCode::Store => {
+                let store_address = self.address(&decoded, 1)?;
+                match self.inputs.pop_front() {
+                    Some(input) => {
+                        self.memory.insert(store_address, input);
+                    },
+                    None => {
+                        // the cursor stays on the store, the next run executes it again
+                        self.state = State::AwaitingInput;
+                        return Ok(())
+                    }
+                }
+            },
+            OpCode::Output => {
+                let value = self.operand(&decoded, 1)?;
+                self.outputs.push(value);
+                if self.resume_mode == ResumeMode::Enable {
+                    self.state = State::Paused;
+                }
+            },
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                let value_checked = self.operand(&decoded, 1)?;
+                let target = self.operand(&decoded, 2)?;
+                let is_zero = value_checked == BigInt::default();
+                if is_zero == (opcode == OpCode::JumpIfFalse) {
+                    self.instruction_cursor = to_i64(target)?;
+                    increment = 0;
+                }
+            },
+            OpCode::Base => {
+                let offset = to_i64(self.operand(&decoded, 1)?)?;
+                self.base = self.base.checked_add(offset).ok_or_else(|| decoded.overflow())?;
+            },
+            OpCode::Stop => {
+                self.state = State::Stopped;
+                increment = 1;
+            },
+            OpCode::Custom(_) => return Err(IntcodeError::UnknownOpCode { instruction_pointer, opcode: opcode_raw })
+        }
+
+        self.instruction_cursor = self.instruction_cursor.checked_add(increment).ok_or_else(|| decoded.overflow())?;
+        Ok(())
+    }
+
+    /// Value of the parameter at the 1-based `position`
+    fn parameter(&self, decoded: &Decoded, position: usize) -> Result<&BigInt, IntcodeError> {
+        parameter_cell(decoded.instruction_pointer, position)
+            .and_then(|address| self.memory.get(&address))
+            .ok_or(IntcodeError::MissingParameter {
+                instruction_pointer: decoded.instruction_pointer,
+                opcode: decoded.opcode_raw,
+                parameter: position
+            })
+    }
+
+    /// Address the parameter refers to, resolved like `Computer` does but failing instead of wrapping around
+    fn address(&self, decoded: &Decoded, position: usize) -> Result<i64, IntcodeError> {
+        let parameter = i64::try_from(self.parameter(decoded, position)?).map_err(|_| decoded.overflow())?;
+        match parameter_address(decoded.modes[position - 1], parameter, self.base) {
+            (address, false) => Ok(address),
+            (_, true) => Err(decoded.overflow())
+        }
+    }
+
+    /// Operand read by the instruction, the cells read are created like in `Computer`
+    fn operand(&mut self, decoded: &Decoded, position: usize) -> Result<BigInt, IntcodeError> {
+        if decoded.modes[position - 1] == Mode::Immediate {
+            return self.parameter(decoded, position).cloned()
+        }
+        let address = self.address(decoded, position)?;
+        Ok(self.memory.entry(address).or_default().clone())
+    }
+}
+
+/// The instruction being executed
+struct Decoded {
+    instruction_pointer: i64,
+    opcode_raw: i64,
+    modes: [Mode; 3]
+}
+
+impl Decoded {
+    fn overflow(&self) -> IntcodeError {
+        IntcodeError::Overflow { instruction_pointer: self.instruction_pointer, opcode: self.opcode_raw }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bignum::BigComputer;
+    use crate::computer::{Computer, IntcodeError, ResumeMode, State};
+    use num_bigint::BigInt;
+
+    /// Runs the program on both computers and checks they end in the same state
+    fn run_both(program: &[i64], inputs: &[i64]) {
+        let mut computer = Computer::new(program.to_vec());
+        let mut big = BigComputer::from_program(program);
+        for input in inputs {
+            computer.add_input(*input);
+            big.add_input(*input);
+        }
+        assert_eq!(big.run(), computer.run(), "{:?}", program);
+
+        let outputs: Vec<BigInt> = computer.outputs().iter().map(|value| BigInt::from(*value)).collect();
+        assert_eq!(big.outputs(), &outputs[..], "{:?}", program);
+        let mut cells: Vec<(i64, BigInt)> = big.memory.iter().map(|(address, value)| (*address, value.clone())).collect();
+        cells.sort();
+        let expected: Vec<(i64, BigInt)> = computer.memory().cells().into_iter().map(|(address, value)| (address, BigInt::from(value))).collect();
+        assert_eq!(cells, expected, "{:?}", program);
+        assert_eq!(big.instruction_pointer(), computer.instruction_pointer(), "{:?}", program);
+    }
+
+    #[test]
+    fn test_same_behaviour_as_computer() {
+        // results at immediate parameters, relative addresses and reads of unwritten cells
+        run_both(&[11101, 2, 3, 5, 99, 0], &[]);
+        run_both(&[109, 3, 21101, 4, 38, -1, 1105, 1, 9, 99, 7], &[]);
+        run_both(&[109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99], &[]);
+        run_both(&[4, 100, 99], &[]);
+        run_both(&[3, 9, 8, 9, 10, 9, 4, 9, 99, -1, 8], &[8]);
+        run_both(&[3, 3, 1107, -1, 8, 3, 4, 3, 99], &[5]);
+        run_both(&[3, 12, 6, 12, 15, 1, 13, 14, 13, 4, 13, 99, -1, 0, 1, 9], &[0]);
+        run_both(&[203, 1000, 99], &[]);
+        // failures
+        run_both(&[1, 0, 0, 0, 5], &[]);
+        run_both(&[1101, 1, 1], &[]);
+        run_both(&[1, 0, 0, 0, 42], &[]);
+        run_both(&[3101, 0, 0, 0, 99], &[]);
+        run_both(&[1105, 1, 10], &[]);
+        // the digits past the modes are not part of the opcode and are reported with it
+        run_both(&[1000042, 0, 0, 0, 99], &[]);
+        run_both(&[4200001, 0, 0, 0, 99], &[]);
+    }
+
+    #[test]
+    fn test_values_past_64_bits() {
+        let mut computer = BigComputer::from_program(&[1102, 34915192, 34915192, 7, 4, 7, 99, 0]);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[BigInt::from(1219070632396864_i64)]);
+
+        // squares the input twice then outputs it
+        let mut computer = BigComputer::from_program(&[3, 13, 2, 13, 13, 13, 2, 13, 13, 13, 4, 13, 99, 0]);
+        computer.add_input(1_i64 << 20);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[BigInt::from(1) << 80]);
+        assert_eq!(computer.outputs()[0].to_string(), "1208925819614629174706176");
+    }
+
+    #[test]
+    fn test_quine_and_resume() {
+        let program = vec![109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99];
+        let mut computer = BigComputer::from_program(&program);
+        computer.set_resume_mode(ResumeMode::Enable);
+        let mut outputs = vec![];
+        while computer.run().unwrap() == State::Paused {
+            outputs.extend(computer.drain_outputs());
+        }
+        let expected: Vec<BigInt> = program.into_iter().map(BigInt::from).collect();
+        assert_eq!(outputs, expected);
+
+        let mut computer = BigComputer::from_program(&[3, 5, 4, 5, 99, 0]);
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+        computer.add_input(BigInt::from(u64::MAX) * 3);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.peek(5), BigInt::from(u64::MAX) * 3);
+    }
+
+    #[test]
+    fn test_addresses_must_fit_in_64_bits() {
+        let mut computer = BigComputer::from_program(&[1105, 1, 7, 99]);
+        computer.poke(2, BigInt::from(i64::MAX) + 1);
+        assert_eq!(computer.run(), Err(IntcodeError::Overflow { instruction_pointer: 0, opcode: 1105 }));
+
+        let mut computer = BigComputer::from_program(&[1, 0, 0, 0, 5]);
+        assert_eq!(computer.run(), Err(IntcodeError::MissingParameter { instruction_pointer: 4, opcode: 5, parameter: 1 }));
+
+        let mut computer = BigComputer::from_program(&[99]);
+        computer.poke(0, BigInt::from(10).pow(20) + 1101);
+        assert_eq!(computer.run(), Err(IntcodeError::Overflow { instruction_pointer: 0, opcode: 1101 }));
+    }
+}
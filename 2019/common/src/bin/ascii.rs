@@ -0,0 +1,22 @@
+use std::env;
+use std::path::PathBuf;
+use common::ascii::AsciiComputer;
+use common::computer::read_program_file;
+use common::loader::exit_with;
+
+const USAGE: &str = "Usage: ascii <program file>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let path = match &args[..] {
+        [path] => PathBuf::from(path),
+        _ => exit_with(USAGE.to_string())
+    };
+
+    let program = read_program_file(path)
+        .unwrap_or_else(|err| exit_with(format!("Unable to read the program: {}", err)));
+    match AsciiComputer::new(program).interactive() {
+        Ok(state) => eprintln!("state {:?}", state),
+        Err(err) => exit_with(format!("The program failed: {}", err))
+    }
+}
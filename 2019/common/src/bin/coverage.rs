@@ -0,0 +1,61 @@
+use std::env;
+use std::path::PathBuf;
+use common::computer::{read_program_file, Computer};
+use common::coverage::Coverage;
+use common::profile::Profile;
+use common::loader::exit_with;
+
+const USAGE: &str = "\
+Usage: coverage [--csv] <program file> [run inputs...]
+Each run inputs argument is a comma separated list of the inputs of one run, for instance
+`coverage program.txt 1 5` runs the program twice, once with 1 and once with 5.";
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let csv = args.first().map(|arg| arg == "--csv").unwrap_or(false);
+    if csv {
+        args.remove(0);
+    }
+    if args.is_empty() {
+        exit_with(USAGE.to_string());
+    }
+
+    let program = read_program_file(PathBuf::from(&args[0]))
+        .unwrap_or_else(|err| exit_with(format!("Unable to read the program: {}", err)));
+    let mut runs: Vec<&str> = args[1..].iter().map(|arg| arg.as_str()).collect();
+    if runs.is_empty() {
+        runs.push("");
+    }
+
+    let mut profile = Profile::default();
+    for run in runs {
+        let mut computer = Computer::new(program.clone());
+        computer.enable_profile();
+        for input in run.split(',').filter(|input| !input.is_empty()) {
+            match input.trim().parse::<i64>() {
+                Ok(input) => computer.add_input(input),
+                Err(_) => exit_with(format!("Invalid input {}", input))
+            }
+        }
+        match computer.run() {
+            Ok(state) => eprintln!("run [{}]: state {:?}, outputs {:?}", run, state, computer.outputs()),
+            Err(err) => eprintln!("run [{}]: the program failed: {}", run, err)
+        }
+        profile.merge(computer.profile().unwrap());
+    }
+
+    let coverage = Coverage::new(&program, &profile);
+    if csv {
+        print!("{}", coverage.summary());
+        return;
+    }
+
+    println!("{}", coverage.annotated_listing());
+    let [code, operands, data, unused] = coverage.totals();
+    println!("\n{} instructions executed ({} operand cells), {} data cells, {} cells unused", code, operands, data, unused);
+    let uncovered = coverage.uncovered_branches();
+    println!("{} branches, {} of them always or never jumped", coverage.branches.len(), uncovered.len());
+    for branch in uncovered {
+        println!("  {}: taken {}, not taken {}", branch.address, branch.taken, branch.not_taken);
+    }
+}
@@ -0,0 +1,44 @@
+use std::env;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::process;
+use common::computer::read_program_file;
+use common::debugger::Debugger;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("Usage: debugger <program file>");
+            process::exit(1);
+        }
+    };
+
+    let program = match read_program_file(path) {
+        Ok(program) => program,
+        Err(err) => {
+            eprintln!("Unable to read the program: {}", err);
+            process::exit(1);
+        }
+    };
+
+    let mut debugger = Debugger::new(program);
+    println!("{}", debugger.execute("list"));
+
+    let stdin = io::stdin();
+    loop {
+        print!("(intcode) ");
+        io::stdout().flush().unwrap();
+
+        let mut command = String::new();
+        match stdin.lock().read_line(&mut command) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+
+        match command.trim() {
+            "q" | "quit" => break,
+            command => println!("{}", debugger.execute(command))
+        }
+    }
+}
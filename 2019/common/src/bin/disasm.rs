@@ -0,0 +1,23 @@
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use common::computer::read_program_file;
+use common::disasm::listing;
+
+fn main() {
+    let path = match env::args().nth(1) {
+        Some(path) => PathBuf::from(path),
+        None => {
+            eprintln!("Usage: disasm <program file>");
+            process::exit(1);
+        }
+    };
+
+    match read_program_file(path) {
+        Ok(program) => println!("{}", listing(&program)),
+        Err(err) => {
+            eprintln!("Unable to read the program: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::env;
+use std::path::PathBuf;
+use common::computer::{read_program_file, Computer};
+use common::disasm::decode;
+use common::loader::exit_with;
+
+const USAGE: &str = "Usage: profile <program file> [inputs...]";
+
+/// Entries shown in each section of the report
+const TOP: usize = 15;
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.is_empty() {
+        exit_with(USAGE.to_string());
+    }
+
+    let program = read_program_file(PathBuf::from(&args[0]))
+        .unwrap_or_else(|err| exit_with(format!("Unable to read the program: {}", err)));
+    let mut computer = Computer::new(program.clone());
+    computer.enable_profile();
+    for input in args[1..].iter() {
+        match input.parse::<i64>() {
+            Ok(input) => computer.add_input(input),
+            Err(_) => exit_with(format!("Invalid input {}", input))
+        }
+    }
+
+    match computer.run() {
+        Ok(state) => println!("state {:?}, outputs {:?}", state, computer.outputs()),
+        Err(err) => println!("The program failed: {}", err)
+    }
+
+    // Instructions are shown as loaded, a program rewriting itself may have run something else
+    let describe = |address: i64| match decode(|address| program.get(address as usize).copied(), address) {
+        Some(line) => line.to_string(),
+        None => format!("{:>5}: ?", address)
+    };
+    print!("{}", computer.profile().unwrap().report(TOP, describe));
+}
@@ -0,0 +1,53 @@
+use std::env;
+use std::path::PathBuf;
+use common::computer::{read_program_file, Computer};
+use common::trace::{Trace, replay};
+use common::loader::exit_with;
+
+const USAGE: &str = "\
+Usage: trace record <program file> <trace file> [inputs...]
+       trace replay <trace file>
+       trace show <trace file>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.iter().map(|x| x.as_str()).collect::<Vec<&str>>()[..] {
+        ["record", program, trace_file, ref inputs @ ..] => {
+            let program = read_program_file(PathBuf::from(program))
+                .unwrap_or_else(|err| exit_with(format!("Unable to read the program: {}", err)));
+            let mut computer = Computer::new(program);
+            computer.enable_trace();
+            for input in inputs {
+                match input.parse::<i64>() {
+                    Ok(input) => computer.add_input(input),
+                    Err(_) => exit_with(format!("Invalid input {}", input))
+                }
+            }
+            if let Err(err) = computer.run() {
+                eprintln!("The program failed: {}", err);
+            }
+            let trace = computer.take_trace().unwrap();
+            trace.save(PathBuf::from(trace_file))
+                .unwrap_or_else(|err| exit_with(format!("Unable to write the trace: {}", err)));
+            println!("{} steps recorded, state {:?}, outputs {:?}", trace.entries.len(), computer.state, trace.outputs());
+        },
+        ["replay", trace_file] => {
+            let trace = Trace::load(PathBuf::from(trace_file))
+                .unwrap_or_else(|err| exit_with(format!("Unable to read the trace: {}", err)));
+            match replay(&trace) {
+                Ok(steps) => println!("{} steps replayed identically", steps),
+                Err(divergence) => exit_with(format!("Replay diverges at {}", divergence))
+            }
+        },
+        ["show", trace_file] => {
+            let trace = Trace::load(PathBuf::from(trace_file))
+                .unwrap_or_else(|err| exit_with(format!("Unable to read the trace: {}", err)));
+            println!("start at {} with base {}, {} memory cells", trace.instruction_pointer, trace.base, trace.memory.len());
+            for entry in trace.entries.iter() {
+                println!("{}", entry);
+            }
+        },
+        _ => exit_with(USAGE.to_string())
+    }
+}
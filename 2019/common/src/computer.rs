@@ -1,59 +1,128 @@
 use std::path::PathBuf;
+use std::convert::TryFrom;
 use std::error::Error;
-use std::io::{BufReader, BufRead};
-use std::fs::File;
-use std::collections::{VecDeque, HashMap};
+use std::collections::{VecDeque, HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+use std::fmt;
+use std::ops::Range;
+use crate::trace::{Trace, TraceEntry};
+use crate::snapshot::Snapshot;
+use crate::memory::{Memory, MemoryKind};
+use crate::io::{InputSource, InputError, OutputSink};
+use crate::profile::{self, Profile};
+use crate::loader::load_program_file;
+use std::sync::Arc;
+use crate::registry::{self, Access, InstructionSet};
+use crate::device::Device;
+use crate::history::{History, UndoEntry};
 
 #[macro_use]
 mod macros {
 
-    macro_rules! get_operand {
-        ($memory:expr, $memory_address:expr, $instruction_cursor:expr, $parameter_mode:expr, $text_error:expr, $base:expr) => {
+    macro_rules! get_parameter {
+        ($computer:expr, $offset:expr, $instruction_cursor:expr, $opcode_raw:expr) => {
+            match parameter_cell($instruction_cursor, $offset as usize).and_then(|address| $computer.memory.get(address)) {
+                Some(parameter) => Ok(parameter),
+                None => Err(IntcodeError::MissingParameter {
+                    instruction_pointer: $instruction_cursor,
+                    opcode: $opcode_raw,
+                    parameter: $offset as usize
+                })
+            }
+        };
+    }
 
-            match $parameter_mode {
-                Mode::Immediate => {
-                    *$memory.entry($instruction_cursor + $memory_address as i64).or_insert(0)
-                },
-                Mode::Positional => {
-                    let operand_address= match $memory.get(&($instruction_cursor + $memory_address as i64)) {
-                        Some(&x) => x,
-                        None => panic!("{} (get memory address -- positional): This memory address doesn't exist", $text_error)
-                    };
+    macro_rules! get_operand {
+        ($computer:expr, $memory_address:expr, $instruction_cursor:expr, $parameter_mode:expr, $opcode_raw:expr, $base:expr) => {
 
-                    *$memory.entry(operand_address).or_insert(0)
+            match get_address!($computer, $memory_address, $instruction_cursor, $parameter_mode, $opcode_raw, $base) {
+                Ok(parameter) => match $parameter_mode {
+                    Mode::Immediate => Ok(parameter),
+                    Mode::Positional | Mode::Relative => Ok($computer.load(parameter))
                 },
-                Mode::Relative => {
-
-                let operand_address= match $memory.get(&($instruction_cursor + $memory_address as i64)) {
-                    Some(&x) => x + $base as i64,
-                    None => panic!("{} (get memory address -- relative): This memory address doesn't exist", $text_error)
-                };
-
-                    *$memory.entry(operand_address).or_insert(0)
-                }
+                Err(error) => Err(error)
             }
         };
     }
 
     macro_rules! get_address {
-        ($memory:expr, $offset:expr, $instruction_cursor:expr, $parameter_mode:expr, $base:expr) => {
-            {
-                let address = *$memory.entry($instruction_cursor + $offset as i64).or_insert(-1);
-                match $parameter_mode {
-                    Mode::Immediate | Mode::Positional => {
-                        address
-                    },
-                    Mode::Relative => {
-                        address + $base
-                    }
-                }
+        ($computer:expr, $offset:expr, $instruction_cursor:expr, $parameter_mode:expr, $opcode_raw:expr, $base:expr) => {
+
+            match get_parameter!($computer, $offset, $instruction_cursor, $opcode_raw) {
+                Ok(parameter) => match parameter_address($parameter_mode, parameter, $base) {
+                    (_, true) if $computer.overflow_check => Err(IntcodeError::Overflow {
+                        instruction_pointer: $instruction_cursor,
+                        opcode: $opcode_raw
+                    }),
+                    (address, _) => Ok(address)
+                },
+                Err(error) => Err(error)
             }
         };
     }
 }
 
-#[derive(PartialEq, Debug, Copy, Clone)]
-enum OpCode {
+/// Every way the execution of an Intcode program can fail.
+///
+/// `instruction_pointer` is the address of the faulty instruction, `opcode` the raw value read there
+/// and `parameter` the 1-based position of the parameter at fault.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum IntcodeError {
+    /// The instruction pointer points to a memory address that has never been written
+    OutOfMemory { instruction_pointer: i64 },
+    /// The two lowest digits of the instruction don't match any known opcode
+    UnknownOpCode { instruction_pointer: i64, opcode: i64 },
+    /// The mode digit of a parameter is not one of 0, 1 or 2
+    UnknownMode { instruction_pointer: i64, opcode: i64, parameter: usize },
+    /// The parameter of the instruction is missing from memory
+    MissingParameter { instruction_pointer: i64, opcode: i64, parameter: usize },
+    /// A store instruction has been reached but no input is available and the program can't be resumed
+    MissingInput { instruction_pointer: i64, opcode: i64 },
+    /// The jump instruction brings the machine back to a state it already was in, without reading any input since
+    InfiniteLoop { instruction_pointer: i64, opcode: i64 },
+    /// The result of the instruction doesn't fit in 64 bits, only reported once `Computer::enable_overflow_check` is called
+    Overflow { instruction_pointer: i64, opcode: i64 },
+    /// The input source of a store instruction read something which isn't a value
+    InvalidInput { instruction_pointer: i64, opcode: i64, error: InputError },
+}
+
+impl fmt::Display for IntcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IntcodeError::OutOfMemory { instruction_pointer } => {
+                write!(f, "@{}: the instruction pointer is out of the memory", instruction_pointer)
+            },
+            IntcodeError::UnknownOpCode { instruction_pointer, opcode } => {
+                write!(f, "@{}: unknown opcode in instruction {}", instruction_pointer, opcode)
+            },
+            IntcodeError::UnknownMode { instruction_pointer, opcode, parameter } => {
+                write!(f, "@{}: unknown mode for parameter {} of instruction {}", instruction_pointer, parameter, opcode)
+            },
+            IntcodeError::MissingParameter { instruction_pointer, opcode, parameter } => {
+                write!(f, "@{}: parameter {} of instruction {} doesn't exist", instruction_pointer, parameter, opcode)
+            },
+            IntcodeError::MissingInput { instruction_pointer, opcode } => {
+                write!(f, "@{}: no input available for instruction {}", instruction_pointer, opcode)
+            },
+            IntcodeError::InfiniteLoop { instruction_pointer, opcode } => {
+                write!(f, "@{}: instruction {} loops forever", instruction_pointer, opcode)
+            },
+            IntcodeError::Overflow { instruction_pointer, opcode } => {
+                write!(f, "@{}: instruction {} overflows", instruction_pointer, opcode)
+            },
+            IntcodeError::InvalidInput { instruction_pointer, opcode, error } => {
+                write!(f, "@{}: instruction {} got an {}", instruction_pointer, opcode, error)
+            }
+        }
+    }
+}
+
+impl Error for IntcodeError {}
+
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+pub enum OpCode {
     Add,
     Multiply,
     Stop,
@@ -63,69 +132,78 @@ enum OpCode {
     JumpIfFalse,
     LessThan,
     Equals,
-    Base
+    Base,
+    /// Opcode added to an `InstructionSet`, by its code
+    Custom(i64)
 }
 
 impl OpCode {
-    fn get_increment(opcode: OpCode) -> i64 {
+    /// Cells the instruction spans, 0 for a halt as the machine doesn't go past it
+    pub(crate) fn get_increment(opcode: OpCode, instructions: &InstructionSet) -> i64 {
         match opcode {
-            OpCode::Add | OpCode::Multiply | OpCode::LessThan | OpCode::Equals => 4,
-            OpCode::Store | OpCode::Output | OpCode::Base => 2,
-            OpCode::JumpIfTrue | OpCode::JumpIfFalse => 3,
-            OpCode::Stop => 0
+            OpCode::Stop => 0,
+            _ => instructions.parameter_count(opcode) as i64 + 1
         }
     }
 
-    fn from_str(string : String) -> OpCode {
-
-        match &string[..] {
-            "01" => OpCode::Add,
-            "02" => OpCode::Multiply,
-            "03" => OpCode::Store,
-            "04" => OpCode::Output,
-            "05" => OpCode::JumpIfTrue,
-            "06" => OpCode::JumpIfFalse,
-            "07" => OpCode::LessThan,
-            "08" => OpCode::Equals,
-            "09" => OpCode::Base,
-            "99" => OpCode::Stop,
-            _ => panic!("Unknown opcode {}", string)
+    /// The two lowest digits of the instruction
+    pub fn code(self) -> i64 {
+        match self {
+            OpCode::Add => 1,
+            OpCode::Multiply => 2,
+            OpCode::Store => 3,
+            OpCode::Output => 4,
+            OpCode::JumpIfTrue => 5,
+            OpCode::JumpIfFalse => 6,
+            OpCode::LessThan => 7,
+            OpCode::Equals => 8,
+            OpCode::Base => 9,
+            OpCode::Stop => 99,
+            OpCode::Custom(code) => code
         }
     }
 
-    fn get_opcode_and_modes_from_str(string: i64) -> (Parameter, OpCode) {
+    fn from_code(code: i64) -> Option<OpCode> {
+        registry::standard().definition(code).map(|definition| definition.opcode)
+    }
+
+    /// Splits an instruction into its parameter modes and one of the standard opcodes, using integer arithmetic only
+    pub fn decode(instruction: i64, instruction_pointer: i64) -> Result<(Parameter, OpCode), IntcodeError> {
+        registry::standard().decode(instruction, instruction_pointer)
+    }
+
+    /// Instruction giving these modes to its parameters, `decode` gives them back
+    pub fn encode(self, modes: &[Mode]) -> i64 {
+        modes.iter()
+            .enumerate()
+            .fold(self.code(), |acc, (position, mode)| acc + mode.code() * 10_i64.pow(position as u32 + 2))
+    }
 
-        // sanitize input
-        let input = format!("{:0>5}", string)
-            .chars()
-            .rev()
-            .take(5)
-            .collect::<Vec<char>>();
+}
 
-        let opcode_string = (&input[0..2]).to_vec().into_iter().rev().collect::<String>();
-        let mode_string = (&input[2..5]).to_vec().into_iter().rev().collect::<String>();
+/// Fails with the code when no standard opcode has it
+impl TryFrom<u32> for OpCode {
+    type Error = u32;
 
-        let param = Parameter::from_str(mode_string);
-        let opcode = OpCode::from_str(opcode_string);
-        (param, opcode)
+    fn try_from(x: u32) -> Result<OpCode, u32> {
+        OpCode::from_code(x as i64).ok_or(x)
     }
 }
 
-impl From<u32> for OpCode {
-    fn from(x: u32) -> OpCode {
-        match x {
-            1 => OpCode::Add,
-            2 => OpCode::Multiply,
-            3 => OpCode::Store,
-            4 => OpCode::Output,
-            5 => OpCode::JumpIfTrue,
-            6 => OpCode::JumpIfFalse,
-            7 => OpCode::LessThan,
-            8 => OpCode::Equals,
-            9 => OpCode::Base,
-            99 => OpCode::Stop,
-            _ => panic!("Unknown opcode {}", x)
-        }
+/// Address of the cell holding the parameter at the 1-based `position` of the instruction, `None` past `i64::MAX`
+pub(crate) fn parameter_cell(instruction_pointer: i64, position: usize) -> Option<i64> {
+    instruction_pointer.checked_add(position as i64)
+}
+
+///
+/// Address of the cell a parameter refers to, given the value of the parameter, and whether it overflowed:
+/// the address then wraps around. A result is written at the address given by an immediate parameter,
+/// as if it were positional. Every computer resolves its parameters with it, see `bignum`.
+///
+pub(crate) fn parameter_address(mode: Mode, parameter: i64, base: i64) -> (i64, bool) {
+    match mode {
+        Mode::Immediate | Mode::Positional => (parameter, false),
+        Mode::Relative => parameter.overflowing_add(base)
     }
 }
 
@@ -136,105 +214,292 @@ enum Command {
     ResultAddress,
 }
 
-#[derive(PartialEq, Debug)]
-enum Mode {
+#[derive(PartialEq, Eq, Hash, Debug, Copy, Clone)]
+pub enum Mode {
     Positional,
     Immediate,
     Relative
 }
 
-#[derive(PartialEq, Debug)]
-struct Parameter {
+impl Mode {
+    /// The digit of the mode in an instruction
+    pub fn code(self) -> i64 {
+        match self {
+            Mode::Positional => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2
+        }
+    }
+
+    fn from_code(code: i64) -> Option<Mode> {
+        match code {
+            0 => Some(Mode::Positional),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None
+        }
+    }
+}
+
+/// Modes of the parameters of an instruction
+#[derive(PartialEq, Eq, Debug, Copy, Clone)]
+pub struct Parameter {
     first_operand: Mode,
     second_operand: Mode,
     result_address: Mode
 }
 
 impl Parameter {
-    fn from_str(string: String) -> Parameter {
-
-        if string.len() < 3 {
-            panic!("The mode string is too short, must be at least 3 characters, {}", string)
-        }
-
-
-        let mut result = string
-            .chars()
-            .rev()
-            .take(3)
-            .map(|c| {
-                match c {
-                    '0' => Some(Mode::Positional),
-                    '1' => Some(Mode::Immediate),
-                    '2' => Some(Mode::Relative),
-                    _ => None
-                }
-            })
-            .filter(|x| x.is_some())
-            .map(|x| x.unwrap())
-            .collect::<Vec<Mode>>();
+    /// Modes in the order of the parameters
+    pub fn modes(&self) -> [Mode; 3] {
+        [self.first_operand, self.second_operand, self.result_address]
+    }
 
-        if result.len() < 3 {
-            panic!("The mode vec is too short, must be at least 3 digit")
+    /// Modes of the instruction, from the hundreds digit onwards
+    pub(crate) fn decode(instruction: i64, instruction_pointer: i64) -> Result<Parameter, IntcodeError> {
+        let mut modes = [Mode::Positional; 3];
+        let mut digits = instruction / 100;
+        for (position, mode) in modes.iter_mut().enumerate() {
+            *mode = match Mode::from_code(digits % 10) {
+                Some(mode) => mode,
+                None => return Err(IntcodeError::UnknownMode { instruction_pointer, opcode: instruction, parameter: position + 1 })
+            };
+            digits /= 10;
         }
 
-
-        // I'm sure there is exactly 3 values in the Vec
-        let result_address = result.pop().unwrap();
-        let second = result.pop().unwrap();
-        let first = result.pop().unwrap();
-
-
-        Parameter {
-            first_operand: first,
-            second_operand: second,
-            result_address
-        }
+        Ok(Parameter { first_operand: modes[0], second_operand: modes[1], result_address: modes[2] })
     }
+
 }
 
 // Pause at output the return memory
-#[derive(Debug, Clone, Copy)]
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum ResumeMode {
     Enable,
     Disable
 }
 
-#[derive(PartialEq, Eq, Debug)]
+/// A memory cell changed by an instruction
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct MemoryWrite {
+    pub address: i64,
+    pub old: i64,
+    pub new: i64
+}
+
+/// Operand read from or result written to a device by an instruction, see `device`
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum DeviceAccess {
+    Read { address: i64, value: i64 },
+    Write { address: i64, value: i64 }
+}
+
+/// What happened during a single instruction, see `Computer::step`
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct StepEvent {
+    /// Address of the executed instruction
+    pub instruction_pointer: i64,
+    pub raw_opcode: i64,
+    pub opcode: OpCode,
+    /// Values of the parameters read by the instruction, after mode resolution
+    pub operands: Vec<i64>,
+    pub writes: Vec<MemoryWrite>,
+    /// Reads and writes of the attached devices, in order, they are not part of `writes`
+    pub device_accesses: Vec<DeviceAccess>,
+    /// Value consumed from the input queue
+    pub input: Option<i64>,
+    pub output: Option<i64>,
+    /// Target of a jump when the condition held
+    pub jump: Option<i64>
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
 pub enum State {
     Started,
     Paused,
+    /// A store instruction is waiting for data, the cursor still points to it
+    AwaitingInput,
+    /// The instruction budget or the time limit of the run is exhausted, running again resumes
+    Interrupted,
     Stopped
 }
 
+/// Instructions executed between two checks of the time limit
+const TIME_CHECK_INTERVAL: u64 = 1024;
+
+/// Addresses past this limit are decoded at each execution
+const DECODE_CACHE_LIMIT: i64 = 1 << 20;
+
+/// The cursor, the base and the memory of a computer
+#[derive(Debug)]
+struct MachineState {
+    instruction_cursor: i64,
+    base: i64,
+    /// Forked, so it is shared with a copy-on-write memory
+    memory: Box<dyn Memory>
+}
+
+impl MachineState {
+    fn of(computer: &Computer) -> MachineState {
+        MachineState {
+            instruction_cursor: computer.instruction_cursor,
+            base: computer.base,
+            memory: computer.memory.fork()
+        }
+    }
+
+    fn matches(&self, computer: &Computer) -> bool {
+        self.instruction_cursor == computer.instruction_cursor
+            && self.base == computer.base
+            && self.memory.cells() == computer.memory.cells()
+    }
+}
+
+/// Part of a cell in `memory_hash`, a cell holding 0 counts as a cell never written
+fn cell_hash(address: i64, value: i64) -> u64 {
+    if value == 0 {
+        return 0
+    }
+    let mut hasher = DefaultHasher::new();
+    (address, value).hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Sum of the hashes of the cells, so a write changes it without hashing the whole memory again
+fn memory_hash(memory: &dyn Memory) -> u64 {
+    memory.cells().into_iter().fold(0, |hash, (address, value)| hash.wrapping_add(cell_hash(address, value)))
+}
+
+///
+/// States of computers, by `Computer::state_hash`. The states sharing a hash are kept in full
+/// and compared, so a hash collision doesn't make two different states equal.
+///
+#[derive(Debug, Default)]
+pub(crate) struct StateSet {
+    states: HashMap<u64, Vec<MachineState>>
+}
+
+impl StateSet {
+    /// Adds the state of the computer, returns false if it was already there
+    pub(crate) fn insert(&mut self, computer: &Computer) -> bool {
+        self.insert_hashed(computer.state_hash(), computer)
+    }
+
+    fn insert_hashed(&mut self, hash: u64, computer: &Computer) -> bool {
+        let states = self.states.entry(hash).or_default();
+        if states.iter().any(|state| state.matches(computer)) {
+            return false
+        }
+        states.push(MachineState::of(computer));
+        true
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.states.clear();
+    }
+}
+
+///
+/// States reached by backward jumps since the last input of the run. Only the hashes are kept
+/// until one comes back, the state is then kept in full and the loop is reported once it comes
+/// back again: a real loop is run twice before being reported, a hash collision never is.
+///
+#[derive(Debug, Default)]
+struct LoopDetector {
+    hashes: HashSet<u64>,
+    repeated: StateSet,
+    /// `memory_hash` of the computer, computed at the first backward jump of the run then
+    /// updated with each write
+    memory_hash: Option<u64>
+}
+
+impl LoopDetector {
+    fn is_looping(&mut self, hash: u64, computer: &Computer) -> bool {
+        !self.hashes.insert(hash) && !self.repeated.insert_hashed(hash, computer)
+    }
+
+    fn track(&mut self, writes: &[MemoryWrite]) {
+        if let Some(hash) = self.memory_hash.as_mut() {
+            for write in writes {
+                *hash = hash.wrapping_sub(cell_hash(write.address, write.old)).wrapping_add(cell_hash(write.address, write.new));
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.hashes.clear();
+        self.repeated.clear();
+    }
+}
+
 #[derive(Debug)]
 pub struct Computer {
-    memory: HashMap<i64,i64>,
+    memory: Box<dyn Memory>,
     output_buffer: Vec<i64>,
     input_data: Option<VecDeque<i64>>,
+    /// Read once the queued inputs are consumed
+    input_source: Option<Box<dyn InputSource>>,
+    /// Takes the outputs instead of the buffer
+    output_sink: Option<Box<dyn OutputSink>>,
     pub resume_mode: ResumeMode,
     pub state: State,
     instruction_cursor: i64,
-    base: i64
+    base: i64,
+    trace: Option<Trace>,
+    /// Opcodes the computer understands
+    instructions: Arc<InstructionSet>,
+    /// Instructions already decoded, indexed by address
+    decode_cache: Option<Vec<Option<(Parameter, OpCode)>>>,
+    /// Instructions allowed to each call of `run`
+    instruction_budget: Option<u64>,
+    time_limit: Option<Duration>,
+    loop_detector: Option<LoopDetector>,
+    /// Fail on overflow instead of wrapping around
+    overflow_check: bool,
+    profile: Option<Profile>,
+    history: Option<History>,
+    /// Devices by mapped addresses, the ranges don't overlap
+    devices: Vec<(Range<i64>, Box<dyn Device>)>,
+    /// Device accesses of the instruction being executed
+    device_accesses: Vec<DeviceAccess>,
+    /// Cells created by the instruction being executed, only kept for the history
+    created: Vec<i64>
 }
 
 impl Computer {
     pub fn new (program: Vec<i64>) -> Computer {
+        Computer::with_memory(program, MemoryKind::Dense.create())
+    }
+
+    /// Loads the program in a memory chosen by the caller
+    pub fn with_memory (program: Vec<i64>, mut memory: Box<dyn Memory>) -> Computer {
 
-        // Create a memory map from the program vec
-        let mut memory = HashMap::new();
         for (key, value) in program.into_iter().enumerate() {
-            memory.insert(key as i64, value);
+            memory.set(key as i64, value);
         }
 
         Computer {
             memory,
             output_buffer: vec![],
             input_data: None,
+            input_source: None,
+            output_sink: None,
             resume_mode: ResumeMode::Disable,
             state: State::Started,
             instruction_cursor: 0,
-            base: 0
+            base: 0,
+            trace: None,
+            instructions: InstructionSet::standard(),
+            decode_cache: None,
+            instruction_budget: None,
+            time_limit: None,
+            loop_detector: None,
+            overflow_check: false,
+            profile: None,
+            history: None,
+            devices: vec![],
+            device_accesses: vec![],
+            created: vec![]
         }
     }
 
@@ -245,7 +510,7 @@ impl Computer {
     pub fn is_setup(&self) -> bool {
         match self.state {
             State::Started => false,
-            State::Paused | State::Stopped => true,
+            State::Paused | State::AwaitingInput | State::Interrupted | State::Stopped => true,
         }
     }
 
@@ -261,207 +526,714 @@ impl Computer {
         }
     }
 
-    pub fn run(&mut self) -> (HashMap<i64,i64>, Vec<i64>) {
+    ///
+    /// Independent copy of the machine, to explore several inputs from the same state.
+    /// The copy keeps the queued inputs, the outputs, the instruction set and the limits of the run, but not the
+    /// attached source, sink and devices, the trace, the profile or the history.
+    /// With `MemoryKind::CopyOnWrite` the memory is shared until written, other memories are copied.
+    ///
+    pub fn fork(&self) -> Computer {
+        Computer {
+            memory: self.memory.fork(),
+            output_buffer: self.output_buffer.clone(),
+            input_data: self.input_data.clone(),
+            input_source: None,
+            output_sink: None,
+            resume_mode: self.resume_mode,
+            state: self.state,
+            instruction_cursor: self.instruction_cursor,
+            base: self.base,
+            trace: None,
+            instructions: Arc::clone(&self.instructions),
+            decode_cache: self.decode_cache.as_ref().map(|_| vec![]),
+            instruction_budget: self.instruction_budget,
+            time_limit: self.time_limit,
+            loop_detector: self.loop_detector.as_ref().map(|_| LoopDetector::default()),
+            overflow_check: self.overflow_check,
+            profile: None,
+            history: None,
+            devices: vec![],
+            device_accesses: vec![],
+            created: vec![]
+        }
+    }
 
-        self.state = State::Started;
+    /// Feeds the store instruction once the inputs added with `add_input` are consumed
+    pub fn set_input_source<S: InputSource + 'static>(&mut self, source: S) {
+        self.input_source = Some(Box::new(source));
+    }
 
-        loop {
+    /// Sends the outputs to the sink, they no longer go to the output buffer
+    pub fn set_output_sink<S: OutputSink + 'static>(&mut self, sink: S) {
+        self.output_sink = Some(Box::new(sink));
+    }
 
-            let increment;
+    ///
+    /// Hands the operands read and the results written in `addresses` over to the device, see `device`.
+    /// Returns false and leaves the computer as is if the range overlaps the one of another device.
+    ///
+    pub fn attach_device<D: Device + 'static>(&mut self, addresses: Range<i64>, device: D) -> bool {
+        if self.devices.iter().any(|(mapped, _)| mapped.start < addresses.end && addresses.start < mapped.end) {
+            return false
+        }
+        self.devices.push((addresses, Box::new(device)));
+        true
+    }
 
-            let opcode_raw =  match self.memory.get(&(self.instruction_cursor + Command::OpCode as i64)) {
-                Some(x) => x,
-                None => panic!("OPCODE: This memory address doesn't exist")
-            };
+    /// Captures the whole machine, the trace being recorded, the attached source, sink and devices, the
+    /// instruction set and the limits of the run are not part of it
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory_kind: self.memory.kind(),
+            memory: self.memory.cells(),
+            inputs: self.input_data.iter().flatten().copied().collect(),
+            outputs: self.output_buffer.clone(),
+            instruction_pointer: self.instruction_cursor,
+            base: self.base,
+            state: self.state,
+            resume_mode: self.resume_mode
+        }
+    }
 
-            let (parameters_mode, opcode) = OpCode::get_opcode_and_modes_from_str(opcode_raw.clone());
+    /// Rebuilds a computer in the exact state of the snapshot, with the same memory backend
+    pub fn restore(snapshot: &Snapshot) -> Computer {
+        let mut memory = snapshot.memory_kind.create();
+        for (address, value) in snapshot.memory.iter() {
+            memory.set(*address, *value);
+        }
+        Computer {
+            memory,
+            output_buffer: snapshot.outputs.clone(),
+            input_data: Some(snapshot.inputs.iter().copied().collect()),
+            input_source: None,
+            output_sink: None,
+            resume_mode: snapshot.resume_mode,
+            state: snapshot.state,
+            instruction_cursor: snapshot.instruction_pointer,
+            base: snapshot.base,
+            trace: None,
+            instructions: InstructionSet::standard(),
+            decode_cache: None,
+            instruction_budget: None,
+            time_limit: None,
+            loop_detector: None,
+            overflow_check: false,
+            profile: None,
+            history: None,
+            devices: vec![],
+            device_accesses: vec![],
+            created: vec![]
+        }
+    }
 
-            if opcode == OpCode::Stop {
-                self.state = State::Stopped;
-                self.instruction_cursor += 1;
-                break;
-            };
+    /// Interrupts `run` after this many instructions, `None` removes the budget
+    pub fn set_instruction_budget(&mut self, budget: Option<u64>) {
+        self.instruction_budget = budget;
+    }
 
+    /// Interrupts `run` once it lasts longer than this, `None` removes the limit
+    pub fn set_time_limit(&mut self, limit: Option<Duration>) {
+        self.time_limit = limit;
+    }
 
-            match opcode {
-                OpCode::Add | OpCode::Multiply => {
+    ///
+    /// Makes `run` fail with `InfiniteLoop` when a jump back leads to a state already seen in the run.
+    /// The memory is hashed at the first backward jump of each run, then the hash follows the writes.
+    /// The state of the devices isn't hashed: like an input, a read from a device forgets the states
+    /// seen so far, so a program polling a device such as a `Clock` isn't reported.
+    ///
+    pub fn enable_loop_detection(&mut self) {
+        self.loop_detector = Some(LoopDetector::default());
+    }
 
-                    let operand_1  = get_operand!(self.memory, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, "OPERAND1", self.base);
-                    let operand_2  = get_operand!(self.memory, Command::OperandAddress2, self.instruction_cursor, parameters_mode.second_operand, "OPERAND2", self.base);
+    ///
+    /// Makes additions, multiplications, relative base moves, relative addresses and the move to the next
+    /// instruction fail with `Overflow` when their result doesn't fit in an `i64`. They wrap around otherwise,
+    /// see `bignum` for programs needing larger values.
+    ///
+    pub fn enable_overflow_check(&mut self) {
+        self.overflow_check = true;
+    }
 
-                    let result = match opcode {
-                        OpCode::Add => {
-                            operand_1 + operand_2
-                        },
-                        OpCode::Multiply => operand_1 * operand_2,
-                        _ => panic!("Unknown opcode")
-                    };
+    /// The exact result if there is one, otherwise the wrapped one or an error when overflows are checked
+    fn arithmetic(&self, checked: Option<i64>, wrapped: i64, opcode_raw: i64) -> Result<i64, IntcodeError> {
+        match checked {
+            Some(result) => Ok(result),
+            None if self.overflow_check => Err(IntcodeError::Overflow {
+                instruction_pointer: self.instruction_cursor,
+                opcode: opcode_raw
+            }),
+            None => Ok(wrapped)
+        }
+    }
 
-                    let store_address = get_address!(self.memory, Command::ResultAddress, self.instruction_cursor, parameters_mode.result_address, self.base);
-                    self.memory.insert(store_address, result);
-                    increment = OpCode::get_increment(opcode);
+    /// Hash of the cursor, the base and the memory. Two machines with different hashes are in different
+    /// states, see `StateSet` to tell whether two machines with the same hash are in the same one.
+    pub(crate) fn state_hash(&self) -> u64 {
+        self.state_hash_with(memory_hash(self.memory.as_ref()))
+    }
 
-                },
-                OpCode::Output | OpCode::Store | OpCode::Base => {
-
-                    let address : i64 = get_address!(self.memory, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, self.base);
-                    increment = OpCode::get_increment(opcode);
-                    match opcode {
-                        OpCode::Store => {
-                            match &mut self.input_data {
-                                Some(x) => {
-                                    match &mut x.pop_front() {
-                                        Some(data) => {
-                                            *self.memory.entry(address).or_insert(0) = *data;
-                                        },
-                                        None => panic!("Unable to get value from input Vec")
-                                    }
-                                },
-                                None => panic!("Unable get value to store")
-                            };
-                        },
-                        OpCode::Output => {
-                            let value : i64 = get_operand!(self.memory, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, "ADRRESS", self.base);
-                            self.output_buffer.push(value);
-
-                            match self.resume_mode {
-                                ResumeMode::Enable => {
-                                    self.state = State::Paused;
-                                },
-                                ResumeMode::Disable => {},
-                            }
-                        },
-                        OpCode::Base => {
-                            let address : i64 = get_operand!(self.memory, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, "Base address", self.base);
-                            self.base += address;
+    fn state_hash_with(&self, memory_hash: u64) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        (self.instruction_cursor, self.base, memory_hash).hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn detect_loop(&mut self, event: &StepEvent) -> Result<(), IntcodeError> {
+        if self.loop_detector.is_none() {
+            return Ok(())
+        }
+        let device_read = event.device_accesses.iter().any(|access| matches!(access, DeviceAccess::Read { .. }));
+        let detector = self.loop_detector.as_mut().unwrap();
+        detector.track(&event.writes);
+        if event.input.is_some() || device_read {
+            detector.clear();
+        }
+        match event.jump {
+            Some(target) if target <= event.instruction_pointer => {
+                let mut detector = self.loop_detector.take().unwrap();
+                let memory_hash = *detector.memory_hash.get_or_insert_with(|| memory_hash(self.memory.as_ref()));
+                let hash = self.state_hash_with(memory_hash);
+                let looping = detector.is_looping(hash, self);
+                self.loop_detector = Some(detector);
+                if looping {
+                    return Err(IntcodeError::InfiniteLoop {
+                        instruction_pointer: event.instruction_pointer,
+                        opcode: event.raw_opcode
+                    })
+                }
+                Ok(())
+            },
+            _ => Ok(())
+        }
+    }
+
+    /// Replaces the standard opcodes by the ones of the set, see `registry`
+    pub fn set_instruction_set(&mut self, instructions: Arc<InstructionSet>) {
+        self.instructions = instructions;
+        if let Some(cache) = self.decode_cache.as_mut() {
+            cache.clear();
+        }
+    }
+
+    pub fn instruction_set(&self) -> &Arc<InstructionSet> {
+        &self.instructions
+    }
+
+    /// Keeps the decoded instructions so a loop doesn't decode them again,
+    /// an entry is dropped as soon as its memory cell is written
+    pub fn enable_decode_cache(&mut self) {
+        self.decode_cache = Some(vec![]);
+    }
+
+    fn decode(&mut self, instruction: i64) -> Result<(Parameter, OpCode), IntcodeError> {
+        let address = self.instruction_cursor;
+        let cache = match self.decode_cache.as_mut() {
+            Some(cache) if (0..DECODE_CACHE_LIMIT).contains(&address) => cache,
+            _ => return self.instructions.decode(instruction, address)
+        };
+
+        let index = address as usize;
+        if let Some(Some(decoded)) = cache.get(index) {
+            return Ok(*decoded)
+        }
+        let decoded = self.instructions.decode(instruction, address)?;
+        if index >= cache.len() {
+            cache.resize(index + 1, None);
+        }
+        cache[index] = Some(decoded);
+        Ok(decoded)
+    }
+
+    /// Starts recording every executed instruction from the current state
+    pub fn enable_trace(&mut self) {
+        self.trace = Some(Trace::new(self.memory.cells(), self.instruction_cursor, self.base));
+    }
+
+    /// Stops the recording and hands the trace over
+    pub fn take_trace(&mut self) -> Option<Trace> {
+        self.trace.take()
+    }
+
+    /// Starts counting what the executed instructions do, see `Profile`
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::default());
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Stops profiling and hands the profile over
+    pub fn take_profile(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    ///
+    /// Keeps what each executed instruction changed so it can be undone with `step_back`,
+    /// forgetting the oldest instructions past `limit`
+    ///
+    pub fn enable_history(&mut self, limit: Option<usize>) {
+        self.history = Some(History::new(limit));
+    }
+
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
+
+    /// Stops recording the history and hands it over
+    pub fn take_history(&mut self) -> Option<History> {
+        self.history.take()
+    }
+
+    /// Forgets the instructions recorded so far, for changes the history can't undo
+    pub(crate) fn clear_history(&mut self) {
+        if let Some(history) = self.history.as_mut() {
+            history.clear();
+        }
+    }
+
+    ///
+    /// Undoes the latest instruction of the history: its writes, the cells it created, the cursor, the base and the state.
+    /// The input it consumed is queued again and its output is taken back if it's still the last one
+    /// of the buffer. The instruction is dropped from the trace, a trace starting after it starts again
+    /// from the restored state. The writes to the devices and the profile are not undone.
+    ///
+    pub fn step_back(&mut self) -> Option<UndoEntry> {
+        let entry = self.history.as_mut()?.pop()?;
+        let instruction = &entry.instruction;
+        for write in instruction.writes.iter().rev() {
+            self.write(write.address, write.old);
+        }
+        for address in entry.created.iter() {
+            self.memory.remove(*address);
+        }
+        if let Some(input) = instruction.input {
+            self.input_data.get_or_insert_with(VecDeque::new).push_front(input);
+        }
+        if instruction.output.is_some() && self.output_sink.is_none() && self.output_buffer.last() == instruction.output.as_ref() {
+            self.output_buffer.pop();
+        }
+        self.instruction_cursor = instruction.instruction_pointer;
+        self.base = entry.base;
+        self.state = entry.state;
+        if self.trace.as_mut().is_some_and(|trace| trace.entries.pop().is_none()) {
+            self.enable_trace();
+        }
+        Some(entry)
+    }
+
+    /// Steps back to the latest instruction which wrote the cell and returns it, `None` leaves the
+    /// computer as is if the history doesn't hold such an instruction
+    pub fn rewind_to_write(&mut self, address: i64) -> Option<UndoEntry> {
+        self.history.as_ref()?.last_write(address)?;
+        loop {
+            let entry = self.step_back()?;
+            if entry.instruction.writes.iter().any(|write| write.address == address) {
+                return Some(entry)
+            }
+        }
+    }
+
+    /// Executes exactly one instruction and describes what it did
+    ///
+    /// If a store instruction finds no input, nothing is executed: the state becomes
+    /// `State::AwaitingInput`, the cursor doesn't move and the event has no write.
+    pub fn step(&mut self) -> Result<StepEvent, IntcodeError> {
+        let reads = match self.profile {
+            Some(_) => profile::operand_reads(self.memory.as_ref(), &self.instructions, self.instruction_cursor, self.base),
+            None => vec![]
+        };
+        let (base, state) = (self.base, self.state);
+        let event = self.execute()?;
+        if self.state != State::AwaitingInput {
+            if let Some(history) = self.history.as_mut() {
+                history.push(UndoEntry { instruction: TraceEntry::from(&event), base, state, created: std::mem::take(&mut self.created) });
+            }
+            if let Some(trace) = self.trace.as_mut() {
+                trace.record(&event, &self.instructions);
+            }
+            if let Some(profile) = self.profile.as_mut() {
+                profile.record(&event, &reads, self.base, &self.instructions);
+            }
+        }
+        Ok(event)
+    }
+
+    fn execute(&mut self) -> Result<StepEvent, IntcodeError> {
+
+        self.state = State::Started;
+        self.device_accesses.clear();
+        self.created.clear();
+
+        let opcode_raw = match self.memory.get(self.instruction_cursor + Command::OpCode as i64) {
+            Some(x) => x,
+            None => return Err(IntcodeError::OutOfMemory { instruction_pointer: self.instruction_cursor })
+        };
+
+        let (parameters_mode, opcode) = self.decode(opcode_raw)?;
+
+        let mut event = StepEvent {
+            instruction_pointer: self.instruction_cursor,
+            raw_opcode: opcode_raw,
+            opcode,
+            operands: vec![],
+            writes: vec![],
+            device_accesses: vec![],
+            input: None,
+            output: None,
+            jump: None
+        };
+
+        let mut increment = OpCode::get_increment(opcode, &self.instructions);
+
+        match opcode {
+            OpCode::Add => self.combine(parameters_mode, opcode_raw, &mut event, |computer, operand_1, operand_2| {
+                computer.arithmetic(operand_1.checked_add(operand_2), operand_1.wrapping_add(operand_2), opcode_raw)
+            })?,
+            OpCode::Multiply => self.combine(parameters_mode, opcode_raw, &mut event, |computer, operand_1, operand_2| {
+                computer.arithmetic(operand_1.checked_mul(operand_2), operand_1.wrapping_mul(operand_2), opcode_raw)
+            })?,
+            OpCode::LessThan => self.combine(parameters_mode, opcode_raw, &mut event, |_, operand_1, operand_2| {
+                Ok((operand_1 < operand_2) as i64)
+            })?,
+            OpCode::Equals => self.combine(parameters_mode, opcode_raw, &mut event, |_, operand_1, operand_2| {
+                Ok((operand_1 == operand_2) as i64)
+            })?,
+            OpCode::Store => {
+                let address : i64 = get_address!(self, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, opcode_raw, self.base)?;
+                let input = match self.input_data.as_mut().and_then(|x| x.pop_front()) {
+                    Some(data) => Some(data),
+                    None => self.input_source.as_mut().and_then(|source| source.next_input())
+                };
+                match input {
+                    Some(data) => {
+                        event.input = Some(data);
+                        event.writes.extend(self.store(address, data));
+                    },
+                    None => {
+                        if let Some(error) = self.input_source.as_mut().and_then(|source| source.take_error()) {
+                            return Err(IntcodeError::InvalidInput { instruction_pointer: self.instruction_cursor, opcode: opcode_raw, error })
                         }
-                        _ => panic!("Unknown opcode")
+                        // Suspend without moving the cursor, the store will be replayed on resume
+                        self.state = State::AwaitingInput;
+                        return Ok(event);
                     }
+                };
+            },
+            OpCode::Output => {
+                let value : i64 = get_operand!(self, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, opcode_raw, self.base)?;
+                event.operands = vec![value];
+                self.output(value, &mut event);
+            },
+            OpCode::Base => {
+                let address : i64 = get_operand!(self, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, opcode_raw, self.base)?;
+                event.operands = vec![address];
+                self.base = self.arithmetic(self.base.checked_add(address), self.base.wrapping_add(address), opcode_raw)?;
+            },
+            OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
+                let value_checked : i64 = get_operand!(self, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, opcode_raw, self.base)?;
+                let next_cursor_address : i64 = get_operand!(self, Command::OperandAddress2, self.instruction_cursor, parameters_mode.second_operand, opcode_raw, self.base)?;
+                event.operands = vec![value_checked, next_cursor_address];
+                let condition_valid = (value_checked != 0) == (opcode == OpCode::JumpIfTrue);
 
-                },
-                OpCode::JumpIfTrue | OpCode::JumpIfFalse => {
-                    let value_checked : i64 = get_operand!(self.memory, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, "VALUE CHECKED", self.base);
-                    let next_cursor_address : i64 = get_operand!(self.memory, Command::OperandAddress2, self.instruction_cursor, parameters_mode.second_operand, "NEXT CURSOR ADDRESS", self.base);
-                    let condition_valid: bool = match opcode {
-                        OpCode::JumpIfTrue => {
-                            if value_checked != 0 {
-                                true
-                            } else {
-                                false
-                            }
-                        },
-                        OpCode::JumpIfFalse => {
-                            if value_checked == 0 {
-                                true
-                            } else {
-                                false
-                            }
+                if condition_valid {
+
+                    self.instruction_cursor = next_cursor_address;
+                    event.jump = Some(next_cursor_address);
+                    increment = 0;
+                }
+            },
+            OpCode::Stop => {
+                self.state = State::Stopped;
+                // the cursor goes past the halt so that running again fails instead of halting again
+                increment = 1;
+            },
+            OpCode::Custom(code) => {
+                let instructions = Arc::clone(&self.instructions);
+                let definition = match instructions.definition(code) {
+                    Some(definition) => definition,
+                    None => return Err(IntcodeError::UnknownOpCode { instruction_pointer: self.instruction_cursor, opcode: opcode_raw })
+                };
+                increment = definition.parameters.len() as i64 + 1;
+
+                let modes = parameters_mode.modes();
+                let mut result_addresses = vec![];
+                for (position, access) in definition.parameters.iter().enumerate() {
+                    let offset = position + 1;
+                    match access {
+                        Access::Read => {
+                            let operand = get_operand!(self, offset, self.instruction_cursor, modes[position], opcode_raw, self.base)?;
+                            event.operands.push(operand);
                         },
-                        _ => panic!("Unknown opcode")
-                    };
+                        Access::Write => {
+                            result_addresses.push(get_address!(self, offset, self.instruction_cursor, modes[position], opcode_raw, self.base)?);
+                        }
+                    }
+                }
 
-                    if condition_valid {
+                let effect = definition.handler.as_ref().map(|handler| handler(&event.operands)).unwrap_or_default();
+                for (address, value) in result_addresses.into_iter().zip(effect.results) {
+                    event.writes.extend(self.store(address, value));
+                }
+                if let Some(value) = effect.output {
+                    self.output(value, &mut event);
+                }
+                if let Some(target) = effect.jump {
+                    self.instruction_cursor = target;
+                    event.jump = Some(target);
+                    increment = 0;
+                }
+            }
+        }
 
-                        self.instruction_cursor = next_cursor_address;
-                        increment = 0;
-                    } else {
-                        increment = OpCode::get_increment(opcode);
-                    }
-                },
-                OpCode::Equals |OpCode::LessThan => {
-
-                    let operand_1 : i64 = get_operand!(self.memory, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, "OPERAND 1", self.base);
-                    let operand_2 : i64 = get_operand!(self.memory, Command::OperandAddress2, self.instruction_cursor, parameters_mode.second_operand, "OPERAND 2", self.base);
-                    let result_address : i64 = get_address!(self.memory, Command::ResultAddress, self.instruction_cursor, parameters_mode.result_address, self.base);
-
-                    let result = match opcode {
-                        OpCode::Equals => {
-                            if operand_1 == operand_2 {
-                                1
-                            } else {
-                                0
-                            }
-                        },
-                        OpCode::LessThan => {
-                            if operand_1 < operand_2 {
-                                1
-                            } else {
-                                0
-                            }
-                        },
-                        _ => panic!("Unknown opcode")
-                    };
+        event.device_accesses = std::mem::take(&mut self.device_accesses);
+        self.instruction_cursor = self.arithmetic(self.instruction_cursor.checked_add(increment), self.instruction_cursor.wrapping_add(increment), opcode_raw)?;
+
+        Ok(event)
+    }
+
+    /// Executes an instruction storing the result of `f` on its two operands
+    fn combine<F>(&mut self, parameters_mode: Parameter, opcode_raw: i64, event: &mut StepEvent, f: F) -> Result<(), IntcodeError>
+        where F: FnOnce(&Computer, i64, i64) -> Result<i64, IntcodeError> {
+
+        let operand_1 : i64 = get_operand!(self, Command::OperandAddress1, self.instruction_cursor, parameters_mode.first_operand, opcode_raw, self.base)?;
+        let operand_2 : i64 = get_operand!(self, Command::OperandAddress2, self.instruction_cursor, parameters_mode.second_operand, opcode_raw, self.base)?;
+        let result = f(self, operand_1, operand_2)?;
+        let result_address : i64 = get_address!(self, Command::ResultAddress, self.instruction_cursor, parameters_mode.result_address, opcode_raw, self.base)?;
 
-                    *self.memory.entry(result_address).or_insert(0) = result;
-                    increment = OpCode::get_increment(opcode);
+        event.operands = vec![operand_1, operand_2];
+        event.writes.extend(self.store(result_address, result));
+        Ok(())
+    }
+
+    /// Sends the value to the sink or the buffer, pausing the machine in resume mode
+    fn output(&mut self, value: i64, event: &mut StepEvent) {
+        match self.output_sink.as_mut() {
+            Some(sink) => sink.send_output(value),
+            None => self.output_buffer.push(value)
+        }
+        event.output = Some(value);
+
+        match self.resume_mode {
+            ResumeMode::Enable => {
+                self.state = State::Paused;
+            },
+            ResumeMode::Disable => {},
+        }
+    }
+
+    /// Address of the next instruction to execute
+    pub fn instruction_pointer(&self) -> i64 {
+        self.instruction_cursor
+    }
+
+    /// Current relative base
+    pub fn base(&self) -> i64 {
+        self.base
+    }
+
+    pub(crate) fn set_instruction_pointer(&mut self, address: i64) {
+        self.instruction_cursor = address;
+    }
+
+    pub(crate) fn set_base(&mut self, base: i64) {
+        self.base = base;
+    }
+
+    /// Value of a memory cell, 0 if it has never been written. Doesn't allocate the cell.
+    pub fn peek(&self, address: i64) -> i64 {
+        self.memory.get(address).unwrap_or(0)
+    }
+
+    /// Values of consecutive cells
+    pub fn peek_range(&self, addresses: Range<i64>) -> Vec<i64> {
+        addresses.map(|address| self.peek(address)).collect()
+    }
+
+    ///
+    /// Writes a memory cell and returns its previous value, 0 if it had never been written.
+    /// A device mapped at the address isn't written, only the memory underneath. The history
+    /// can't undo the change, so it's cleared.
+    ///
+    pub fn poke(&mut self, address: i64, value: i64) -> i64 {
+        self.clear_history();
+        self.write(address, value).old
+    }
+
+    ///
+    /// Writes the values from `address` onwards, to patch the program or load data, see `poke`.
+    /// Returns false and leaves the computer as is if the last value would go past `i64::MAX`.
+    ///
+    pub fn load_at(&mut self, address: i64, values: &[i64]) -> bool {
+        let fits = i64::try_from(values.len()).ok()
+            .and_then(|count| address.checked_add(count - 1))
+            .is_some();
+        if !values.is_empty() && !fits {
+            return false
+        }
+        self.clear_history();
+        for (offset, value) in values.iter().enumerate() {
+            self.write(address + offset as i64, *value);
+        }
+        true
+    }
+
+    /// Operand read by an instruction, from the device mapped at this address if any
+    fn load(&mut self, address: i64) -> i64 {
+        match self.devices.iter_mut().find(|(addresses, _)| addresses.contains(&address)) {
+            Some((addresses, device)) => {
+                let value = device.read(address - addresses.start);
+                self.device_accesses.push(DeviceAccess::Read { address, value });
+                value
+            },
+            None => match self.memory.get(address) {
+                Some(value) => value,
+                None => {
+                    self.memory.set(address, 0);
+                    if self.history.is_some() {
+                        self.created.push(address);
+                    }
+                    0
                 }
-                _ => {
-                    println!("{:?}", opcode);
-                    panic!("Unknown opcode")
+            }
+        }
+    }
+
+    /// Result written by an instruction, a write to a device leaves the memory as is and isn't returned
+    fn store(&mut self, address: i64, value: i64) -> Option<MemoryWrite> {
+        match self.devices.iter_mut().find(|(addresses, _)| addresses.contains(&address)) {
+            Some((addresses, device)) => {
+                device.write(address - addresses.start, value);
+                self.device_accesses.push(DeviceAccess::Write { address, value });
+                None
+            },
+            None => Some(self.write(address, value))
+        }
+    }
+
+    /// Stores the value and keeps track of what was overwritten
+    pub(crate) fn write(&mut self, address: i64, value: i64) -> MemoryWrite {
+        if let Some(cache) = self.decode_cache.as_mut() {
+            if (0..cache.len() as i64).contains(&address) {
+                // self-modifying code, the instruction must be decoded again
+                cache[address as usize] = None;
+            }
+        }
+        let old = match self.memory.set(address, value) {
+            Some(old) => old,
+            None => {
+                if self.history.is_some() {
+                    self.created.push(address);
                 }
+                0
             }
+        };
+        MemoryWrite { address, old, new: value }
+    }
+
+    /// Outputs produced and not drained yet
+    pub fn outputs(&self) -> &[i64] {
+        &self.output_buffer
+    }
 
-            self.instruction_cursor += increment;
+    pub fn last_output(&self) -> Option<i64> {
+        self.output_buffer.last().copied()
+    }
 
+    /// Hands the outputs over, leaving the buffer empty
+    pub fn drain_outputs(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.output_buffer)
+    }
+
+    pub fn memory(&self) -> &dyn Memory {
+        self.memory.as_ref()
+    }
+
+    /// Runs until the program halts, pauses on output, waits for input or exhausts a limit, and tells which one
+    pub fn run(&mut self) -> Result<State, IntcodeError> {
+
+        let start = Instant::now();
+        let mut executed: u64 = 0;
+        let mut next_time_check: u64 = 0;
+        if let Some(detector) = self.loop_detector.as_mut() {
+            // the memory may have been written outside of a run, its hash is computed again
+            *detector = LoopDetector::default();
+        }
+
+        loop {
+
+            if self.instruction_budget.is_some_and(|budget| executed >= budget) {
+                self.state = State::Interrupted;
+                break;
+            }
+            if executed == next_time_check {
+                next_time_check += TIME_CHECK_INTERVAL;
+                if self.time_limit.is_some_and(|limit| start.elapsed() >= limit) {
+                    self.state = State::Interrupted;
+                    break;
+                }
+            }
+
+            let event = self.step()?;
+            executed += 1;
+            self.detect_loop(&event)?;
 
             match self.state {
-                State::Paused => break,
+                State::Paused | State::AwaitingInput | State::Stopped => break,
                 _ => ()
             }
         }
 
-        (self.memory.clone(), self.output_buffer.clone())
+        Ok(self.state)
+    }
+
+    /// One-shot runs can't be fed later, so waiting for input is an error for them
+    fn run_to_completion(&mut self) -> Result<(HashMap<i64,i64>, Vec<i64>), IntcodeError> {
+        if self.run()? == State::AwaitingInput {
+            return Err(IntcodeError::MissingInput {
+                instruction_pointer: self.instruction_cursor,
+                opcode: self.memory.get(self.instruction_cursor).unwrap_or(0)
+            })
+        }
+        Ok((self.memory.cells().into_iter().collect(), self.drain_outputs()))
     }
 }
 
 ///
-/// Loads program
-///
-///
+/// Loads program, see `loader` for the accepted format
 pub fn read_program_file(path: PathBuf) -> Result<Vec<i64>, Box<dyn Error>> {
-    let file = File::open(&path)?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = String::new();
-    reader.read_line(&mut buffer)?;
-    let result = buffer
-        .split(',')
-        .map(|x| match x.parse::<i64>() {
-            Ok(value) => Some(value),
-            Err(_err) => None
-        })
-        .filter(|x| x.is_some())
-        .map(|x| x.unwrap())
-        .collect();
-
-    Ok(result)
+    load_program_file(path)
 }
 
-pub fn computer(memory :Vec<i64>, input: Option<VecDeque<i64>>) -> (HashMap<i64,i64>, Vec<i64>) {
+pub fn computer(memory :Vec<i64>, input: Option<VecDeque<i64>>) -> Result<(HashMap<i64,i64>, Vec<i64>), IntcodeError> {
 
     let mut computer = Computer::new(memory);
     computer.input_data = input;
 
-    computer.run()
+    computer.run_to_completion()
 }
 
-pub fn computer_feedback(memory :Vec<i64>, input: Option<VecDeque<i64>>) -> (HashMap<i64,i64>, Vec<i64>) {
+pub fn computer_feedback(memory :Vec<i64>, input: Option<VecDeque<i64>>) -> Result<(HashMap<i64,i64>, Vec<i64>), IntcodeError> {
 
     let mut computer = Computer::new(memory);
     computer.resume_mode = ResumeMode::Enable;
     computer.input_data = input;
 
-    computer.run()
+    computer.run_to_completion()
 }
 
 
 #[cfg(test)]
 mod tests {
-    use super::{OpCode, computer, Parameter, Mode, read_program_file};
+    use std::convert::TryFrom;
+    use super::{OpCode, computer, Parameter, Mode, read_program_file, IntcodeError};
     use std::path::PathBuf;
     use std::collections::VecDeque;
-    use crate::computer::{Computer, ResumeMode, State};
+    use crate::computer::{Computer, ResumeMode, State, MemoryWrite, StateSet, LoopDetector, memory_hash};
+    use crate::memory::MemoryKind;
     use std::collections::HashMap;
-    use std::iter::FromIterator;
+    use std::time::Duration;
 
     #[macro_use]
     mod macros {
@@ -481,43 +1253,36 @@ mod tests {
     }
 
 
-    #[test]
-    fn test_opcode_to_increment() {
-        assert_eq!(OpCode::get_increment(OpCode::Add), 4);
-        assert_eq!(OpCode::get_increment(OpCode::Multiply), 4);
-        assert_eq!(OpCode::get_increment(OpCode::Store), 2);
-        assert_eq!(OpCode::get_increment(OpCode::Output), 2);
-        assert_eq!(OpCode::get_increment(OpCode::JumpIfTrue), 3);
-        assert_eq!(OpCode::get_increment(OpCode::JumpIfFalse), 3);
-        assert_eq!(OpCode::get_increment(OpCode::LessThan), 4);
-        assert_eq!(OpCode::get_increment(OpCode::Equals), 4);
-        assert_eq!(OpCode::get_increment(OpCode::Stop), 0);
+    fn memory_map(computer: &Computer) -> HashMap<i64, i64> {
+        computer.memory().cells().into_iter().collect()
     }
 
     #[test]
-    fn test_integer_to_opcode() {
-        assert_eq!(OpCode::from(1), OpCode::Add);
-        assert_eq!(OpCode::from(2), OpCode::Multiply);
-        assert_eq!(OpCode::from(3), OpCode::Store);
-        assert_eq!(OpCode::from(4), OpCode::Output);
-        assert_eq!(OpCode::from(5), OpCode::JumpIfTrue);
-        assert_eq!(OpCode::from(6), OpCode::JumpIfFalse);
-        assert_eq!(OpCode::from(7), OpCode::LessThan);
-        assert_eq!(OpCode::from(8), OpCode::Equals);
-        assert_eq!(OpCode::from(99), OpCode::Stop);
+    fn test_opcode_to_increment() {
+        let instructions = crate::registry::InstructionSet::new();
+        assert_eq!(OpCode::get_increment(OpCode::Add, &instructions), 4);
+        assert_eq!(OpCode::get_increment(OpCode::Multiply, &instructions), 4);
+        assert_eq!(OpCode::get_increment(OpCode::Store, &instructions), 2);
+        assert_eq!(OpCode::get_increment(OpCode::Output, &instructions), 2);
+        assert_eq!(OpCode::get_increment(OpCode::JumpIfTrue, &instructions), 3);
+        assert_eq!(OpCode::get_increment(OpCode::JumpIfFalse, &instructions), 3);
+        assert_eq!(OpCode::get_increment(OpCode::LessThan, &instructions), 4);
+        assert_eq!(OpCode::get_increment(OpCode::Equals, &instructions), 4);
+        assert_eq!(OpCode::get_increment(OpCode::Stop, &instructions), 0);
     }
 
     #[test]
-    fn test_opcode_from_str() {
-        assert_eq!(OpCode::from_str("01".to_string()), OpCode::Add);
-        assert_eq!(OpCode::from_str("02".to_string()), OpCode::Multiply);
-        assert_eq!(OpCode::from_str("03".to_string()), OpCode::Store);
-        assert_eq!(OpCode::from_str("04".to_string()), OpCode::Output);
-        assert_eq!(OpCode::from_str("05".to_string()), OpCode::JumpIfTrue);
-        assert_eq!(OpCode::from_str("06".to_string()), OpCode::JumpIfFalse);
-        assert_eq!(OpCode::from_str("07".to_string()), OpCode::LessThan);
-        assert_eq!(OpCode::from_str("08".to_string()), OpCode::Equals);
-        assert_eq!(OpCode::from_str("99".to_string()), OpCode::Stop);
+    fn test_integer_to_opcode() {
+        assert_eq!(OpCode::try_from(1), Ok(OpCode::Add));
+        assert_eq!(OpCode::try_from(2), Ok(OpCode::Multiply));
+        assert_eq!(OpCode::try_from(3), Ok(OpCode::Store));
+        assert_eq!(OpCode::try_from(4), Ok(OpCode::Output));
+        assert_eq!(OpCode::try_from(5), Ok(OpCode::JumpIfTrue));
+        assert_eq!(OpCode::try_from(6), Ok(OpCode::JumpIfFalse));
+        assert_eq!(OpCode::try_from(7), Ok(OpCode::LessThan));
+        assert_eq!(OpCode::try_from(8), Ok(OpCode::Equals));
+        assert_eq!(OpCode::try_from(99), Ok(OpCode::Stop));
+        assert_eq!(OpCode::try_from(42), Err(42));
     }
 
     #[test]
@@ -525,54 +1290,54 @@ mod tests {
 
         let empty: Vec<i64> = Vec::new();
 
-        assert_eq!(computer( vec![1, 0, 0, 0, 99], None),
+        assert_eq!(computer( vec![1, 0, 0, 0, 99], None).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![2, 0, 0, 0, 99]), empty.clone()), "Must be able to add two numbers");
-        assert_eq!(computer(vec![2, 3, 0, 3, 99], None),
+        assert_eq!(computer(vec![2, 3, 0, 3, 99], None).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![2, 3, 0, 6, 99]), empty.clone()), "Must be able to multiply two numbers");
-        assert_eq!(computer(vec![2, 4, 4, 5, 99, 0], None),
+        assert_eq!(computer(vec![2, 4, 4, 5, 99, 0], None).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![2, 4, 4, 5, 99, 9801]), empty.clone()), "Must be able to multiply two numbers and store the result");
-        assert_eq!(computer(vec![1, 1, 1, 4, 99, 5, 6, 0, 99], None),
+        assert_eq!(computer(vec![1, 1, 1, 4, 99, 5, 6, 0, 99], None).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![30, 1, 1, 4, 2, 5, 6, 0, 99]), empty.clone()), "Must be able to handle complex program");
-        assert_eq!(computer(vec![1101, 100, -1, 4, 0], None),
+        assert_eq!(computer(vec![1101, 100, -1, 4, 0], None).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![1101, 100, -1, 4, 99]), empty.clone()), "Can handle operation immediate value");
-        assert_eq!(computer(vec![3, 0, 4, 0, 99], Some(VecDeque::from(vec![-42]))),
+        assert_eq!(computer(vec![3, 0, 4, 0, 99], Some(VecDeque::from(vec![-42]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![-42, 0, 4, 0, 99]), vec![-42]), "Able to write in output buffer");
 
         // --- Positional mode
         // equality
-        assert_eq!(computer(vec![3,9,8,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![8]))),
+        assert_eq!(computer(vec![3,9,8,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![8]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,9,8,9,10,9,4,9,99,1,8]), vec![1]), "Able to deal with equality (position mode)");
-        assert_eq!(computer(vec![3,9,8,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![12]))),
+        assert_eq!(computer(vec![3,9,8,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![12]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,9,8,9,10,9,4,9,99,0,8]), vec![0]), "Able to deal with non equality (position mode)");
         // less than
-        assert_eq!(computer(vec![3,9,7,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![5]))),
+        assert_eq!(computer(vec![3,9,7,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![5]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,9,7,9,10,9,4,9,99,1,8]), vec![1]), "Able to deal with less than (position mode)");
-        assert_eq!(computer(vec![3,9,7,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![12]))),
+        assert_eq!(computer(vec![3,9,7,9,10,9,4,9,99,-1,8], Some(VecDeque::from(vec![12]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,9,7,9,10,9,4,9,99,0,8]), vec![0]), "Able to deal with greater than (position mode)");
 
         // --- Immediate mode
         // equality
-        assert_eq!(computer(vec![3,3,1108,-1,8,3,4,3,99], Some(VecDeque::from(vec![8]))),
+        assert_eq!(computer(vec![3,3,1108,-1,8,3,4,3,99], Some(VecDeque::from(vec![8]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,3,1108,1,8,3,4,3,99]), vec![1]), "Able to deal with equality (immediate mode)");
-        assert_eq!(computer(vec![3,3,1108,-1,8,3,4,3,99], Some(VecDeque::from(vec![12]))),
+        assert_eq!(computer(vec![3,3,1108,-1,8,3,4,3,99], Some(VecDeque::from(vec![12]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,3,1108,0,8,3,4,3,99]), vec![0]), "Able to deal with non equality (immediate mode)");
         // less than
-        assert_eq!(computer(vec![3,3,1107,-1,8,3,4,3,99], Some(VecDeque::from(vec![5]))),
+        assert_eq!(computer(vec![3,3,1107,-1,8,3,4,3,99], Some(VecDeque::from(vec![5]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,3,1107,1,8,3,4,3,99]), vec![1]), "Able to deal with less than (immediate mode)");
-        assert_eq!(computer(vec![3,3,1107,-1,8,3,4,3,99], Some(VecDeque::from(vec![12]))),
+        assert_eq!(computer(vec![3,3,1107,-1,8,3,4,3,99], Some(VecDeque::from(vec![12]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,3,1107,0,8,3,4,3,99]), vec![0]), "Able to deal with greater than (immediate mode)");
 
         // --- Positional mode
-        assert_eq!(computer(vec![3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9], Some(VecDeque::from(vec![1]))),
+        assert_eq!(computer(vec![3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9], Some(VecDeque::from(vec![1]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,12,6,12,15,1,13,14,13,4,13,99,1,1,1,9]), vec![1]), "Should jump if input 1 (position mode)");
-        assert_eq!(computer(vec![3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9], Some(VecDeque::from(vec![0]))),
+        assert_eq!(computer(vec![3,12,6,12,15,1,13,14,13,4,13,99,-1,0,1,9], Some(VecDeque::from(vec![0]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,12,6,12,15,1,13,14,13,4,13,99,0,0,1,9]), vec![0]), "Should jump if input 0 (position mode)");
 
         // --- Immediate mode
-        assert_eq!(computer(vec![3,3,1105,-1,9,1101,0,0,12,4,12,99,1], Some(VecDeque::from(vec![1]))),
+        assert_eq!(computer(vec![3,3,1105,-1,9,1101,0,0,12,4,12,99,1], Some(VecDeque::from(vec![1]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,3,1105,1,9,1101,0,0,12,4,12,99,1]), vec![1]), "Should jump if input 1 (immediate mode)");
         // --- Positional mode
-        assert_eq!(computer(vec![3,3,1105,-1,9,1101,0,0,12,4,12,99,1], Some(VecDeque::from(vec![0]))),
+        assert_eq!(computer(vec![3,3,1105,-1,9,1101,0,0,12,4,12,99,1], Some(VecDeque::from(vec![0]))).unwrap(),
                    (hashed_map_fill!(i64, i64, vec![3,3,1105,0,9,1101,0,0,12,4,12,99,0]), vec![0]), "Should jump if input 0 (immediate mode)");
 
         let program = vec![3,21,1008,21,8,20,1005,20,22,107,8,21,20,1006,20,31,
@@ -581,48 +1346,66 @@ mod tests {
 
 
         // equal 8
-        let (_, buffer) = computer(program.clone(), Some(VecDeque::from(vec![8])));
+        let (_, buffer) = computer(program.clone(), Some(VecDeque::from(vec![8]))).unwrap();
         let expected : Vec<i64> = vec![1000];
         assert_eq!(buffer, expected);
 
         // greater than 8
-        let (_, buffer) = computer(program.clone(), Some(VecDeque::from(vec![220])));
+        let (_, buffer) = computer(program.clone(), Some(VecDeque::from(vec![220]))).unwrap();
         let expected : Vec<i64> = vec![1001];
         assert_eq!(buffer, expected);
 
         // less than 8
-        let (_, buffer) = computer(program.clone(), Some(VecDeque::from(vec![7])));
+        let (_, buffer) = computer(program.clone(), Some(VecDeque::from(vec![7]))).unwrap();
         let expected : Vec<i64> = vec![999];
         assert_eq!(buffer, expected);
 
         let program = vec![1102,34915192,34915192,7,4,7,99,0];
-        let (_, buffer) = computer(program, None);
-        let result = buffer.last().unwrap().to_string().chars().into_iter().count();
+        let (_, buffer) = computer(program, None).unwrap();
+        let result = buffer.last().unwrap().to_string().chars().count();
         assert_eq!(result, 16);
 
 
     }
 
     #[test]
-    fn test_create_parameters_from_str() {
-        assert_eq!(Parameter::from_str("0000".to_string()), Parameter { first_operand: Mode::Positional, second_operand: Mode::Positional, result_address: Mode::Positional });
-        assert_eq!(Parameter::from_str("001".to_string()), Parameter { first_operand: Mode::Immediate, second_operand: Mode::Positional, result_address: Mode::Positional });
-        assert_eq!(Parameter::from_str("0010".to_string()), Parameter { first_operand: Mode::Positional, second_operand: Mode::Immediate, result_address: Mode::Positional });
-        assert_eq!(Parameter::from_str("011".to_string()), Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Positional });
-        assert_eq!(Parameter::from_str("111".to_string()), Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Immediate });
-        assert_eq!(Parameter::from_str("211".to_string()), Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Relative });
+    fn test_parameter_decode() {
+        assert_eq!(Parameter::decode(1, 0), Ok(Parameter { first_operand: Mode::Positional, second_operand: Mode::Positional, result_address: Mode::Positional }));
+        assert_eq!(Parameter::decode(101, 0), Ok(Parameter { first_operand: Mode::Immediate, second_operand: Mode::Positional, result_address: Mode::Positional }));
+        assert_eq!(Parameter::decode(1001, 0), Ok(Parameter { first_operand: Mode::Positional, second_operand: Mode::Immediate, result_address: Mode::Positional }));
+        assert_eq!(Parameter::decode(1101, 0), Ok(Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Positional }));
+        assert_eq!(Parameter::decode(11101, 0), Ok(Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Immediate }));
+        assert_eq!(Parameter::decode(21101, 0), Ok(Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Relative }));
+        assert_eq!(Parameter::decode(1301, 4), Err(IntcodeError::UnknownMode { instruction_pointer: 4, opcode: 1301, parameter: 1 }));
     }
 
     #[test]
-    fn test_get_opcode_and_modes_from_str() {
-        assert_eq!(OpCode::get_opcode_and_modes_from_str(1),
-                   (Parameter { first_operand: Mode::Positional, second_operand: Mode::Positional, result_address: Mode::Positional }, OpCode::Add));
-        assert_eq!(OpCode::get_opcode_and_modes_from_str(1002),
-                   (Parameter { first_operand: Mode::Positional, second_operand: Mode::Immediate, result_address: Mode::Positional }, OpCode::Multiply));
-        assert_eq!(OpCode::get_opcode_and_modes_from_str(99),
-                   (Parameter { first_operand: Mode::Positional, second_operand: Mode::Positional, result_address: Mode::Positional }, OpCode::Stop));
-        assert_eq!(OpCode::get_opcode_and_modes_from_str(21102),
-                   (Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Relative }, OpCode::Multiply));
+    fn test_decode() {
+        assert_eq!(OpCode::decode(1, 0),
+                   Ok((Parameter { first_operand: Mode::Positional, second_operand: Mode::Positional, result_address: Mode::Positional }, OpCode::Add)));
+        assert_eq!(OpCode::decode(1002, 0),
+                   Ok((Parameter { first_operand: Mode::Positional, second_operand: Mode::Immediate, result_address: Mode::Positional }, OpCode::Multiply)));
+        assert_eq!(OpCode::decode(99, 0),
+                   Ok((Parameter { first_operand: Mode::Positional, second_operand: Mode::Positional, result_address: Mode::Positional }, OpCode::Stop)));
+        assert_eq!(OpCode::decode(21102, 0),
+                   Ok((Parameter { first_operand: Mode::Immediate, second_operand: Mode::Immediate, result_address: Mode::Relative }, OpCode::Multiply)));
+        assert_eq!(OpCode::decode(42, 7), Err(IntcodeError::UnknownOpCode { instruction_pointer: 7, opcode: 42 }));
+        assert_eq!(OpCode::decode(-1, 0), Err(IntcodeError::UnknownOpCode { instruction_pointer: 0, opcode: -1 }));
+        assert_eq!(OpCode::decode(1000202, 0), Ok((Parameter { first_operand: Mode::Relative, second_operand: Mode::Positional, result_address: Mode::Positional }, OpCode::Multiply)));
+    }
+
+    #[test]
+    fn test_decode_cache_is_invalidated_by_writes() {
+        // the instruction at 10 is executed as a mul, then rewritten into an add and executed again
+        let program = vec![1105,1,10, 1101,0,1,10, 1105,1,10, 2,22,22,22, 1008,10,1,23, 1006,23,3, 99, 3, 0];
+        for cache in [false, true] {
+            let mut computer = Computer::new(program.clone());
+            if cache {
+                computer.enable_decode_cache();
+            }
+            computer.run().unwrap();
+            assert_eq!(computer.memory().get(22), Some(18));
+        }
     }
 
     #[test]
@@ -635,7 +1418,7 @@ mod tests {
     #[test]
     fn test_computer_can_store_more_than_one_input() {
         let program = vec![3,5,3,6,99,-1,-1];
-        let (memory, _) = computer(program.clone(), Some(VecDeque::from(vec![220, -42])));
+        let (memory, _) = computer(program.clone(), Some(VecDeque::from(vec![220, -42]))).unwrap();
         assert_eq!(hashed_map_fill!(i64, i64, vec![3,5,3,6,99,220,-42]), memory);
 
     }
@@ -647,22 +1430,24 @@ mod tests {
         let program = vec![104, -42,104,48, 99];
         let mut computer = Computer::new(program.clone());
         computer.set_resume_mode(ResumeMode::Enable);
-        let (memory, buffer) = computer.run();
-        assert_eq!(hashed_map_fill!(i64, i64, vec![104,-42,104,48, 99]), memory);
-        assert_eq!(&-42, buffer.last().unwrap());
+        assert_eq!(computer.run().unwrap(), State::Paused);
+        assert_eq!(hashed_map_fill!(i64, i64, vec![104,-42,104,48, 99]), memory_map(&computer));
+        assert_eq!(Some(-42), computer.last_output());
         assert_eq!(State::Paused, computer.state);
         assert_eq!(2, computer.instruction_cursor);
 
 
-        let (memory, buffer) = computer.run();
-        assert_eq!(hashed_map_fill!(i64, i64, vec![104, -42,104,48, 99]), memory);
-        assert_eq!(&48, buffer.last().unwrap());
+        assert_eq!(computer.run().unwrap(), State::Paused);
+        assert_eq!(hashed_map_fill!(i64, i64, vec![104, -42,104,48, 99]), memory_map(&computer));
+        assert_eq!(Some(48), computer.last_output());
         assert_eq!(State::Paused, computer.state);
         assert_eq!(4, computer.instruction_cursor);
 
 
-        let (memory, buffer) = computer.run();
-        assert_eq!(hashed_map_fill!(i64, i64, vec![104, -42,104,48, 99]), memory);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(hashed_map_fill!(i64, i64, vec![104, -42,104,48, 99]), memory_map(&computer));
+        assert_eq!(vec![-42, 48], computer.drain_outputs());
+        assert!(computer.outputs().is_empty());
         assert_eq!(State::Stopped, computer.state);
         assert_eq!(5, computer.instruction_cursor);
 
@@ -675,24 +1460,24 @@ mod tests {
 
         // first step
         computer.add_input(12);
-        let (memory, buffer) = computer.run();
-        assert_eq!(memory, hashed_map_fill!(i64, i64, vec![3,9,4,9,3,10,4,10,99,12,-1]));
-        assert_eq!(buffer.last().unwrap(), &12);
+        computer.run().unwrap();
+        assert_eq!(memory_map(&computer), hashed_map_fill!(i64, i64, vec![3,9,4,9,3,10,4,10,99,12,-1]));
+        assert_eq!(computer.last_output(), Some(12));
         assert_eq!(computer.state, State::Paused);
         assert_eq!(computer.instruction_cursor, 4);
 
         // second step
         computer.add_input(42);
-        let (memory, buffer) = computer.run();
-        assert_eq!(memory, hashed_map_fill!(i64, i64, vec![3,9,4,9,3,10,4,10,99,12,42]));
-        assert_eq!(buffer.last().unwrap(), &42);
+        computer.run().unwrap();
+        assert_eq!(memory_map(&computer), hashed_map_fill!(i64, i64, vec![3,9,4,9,3,10,4,10,99,12,42]));
+        assert_eq!(computer.last_output(), Some(42));
         assert_eq!(computer.state, State::Paused);
         assert_eq!(computer.instruction_cursor, 8);
 
 
         // halt
-        let (memory, _) = computer.run();
-        assert_eq!(memory, hashed_map_fill!(i64, i64, vec![3,9,4,9,3,10,4,10,99,12,42]));
+        computer.run().unwrap();
+        assert_eq!(memory_map(&computer), hashed_map_fill!(i64, i64, vec![3,9,4,9,3,10,4,10,99,12,42]));
         assert_eq!(computer.state, State::Stopped);
         assert_eq!(computer.instruction_cursor, 9);
 
@@ -703,15 +1488,27 @@ mod tests {
         let program = vec![3, 100000000000000,4,100000000000000,99];
         let mut expected = hashed_map_fill!(i64, i64, program.clone());
         expected.insert(100000000000000, 42);
-        let (memory, buffer) = computer(program, Some(VecDeque::from(vec![42])));
+        let (memory, buffer) = computer(program, Some(VecDeque::from(vec![42]))).unwrap();
         assert_eq!(expected, memory);
         assert_eq!(*buffer.last().unwrap(), 42);
     }
 
+    #[test]
+    fn test_memory_backends_give_the_same_results() {
+        let program = vec![3, 100000000000000,4,100000000000000,109,1,204,-1,99];
+        for kind in [MemoryKind::Dense, MemoryKind::Sparse, MemoryKind::CopyOnWrite] {
+            let mut computer = Computer::with_memory(program.clone(), kind.create());
+            computer.add_input(42);
+            computer.run().unwrap();
+            assert_eq!(computer.memory().get(100000000000000), Some(42));
+            assert_eq!(computer.outputs(), &[42, 3]);
+        }
+    }
+
     #[test]
     fn test_relative_mode() {
         let program = vec![109,1,204,-1,1001,100,1,100,1008,100,16,101,1006,101,0,99];
-        let (_, buffer) = computer(program.clone(), None);
+        let (_, buffer) = computer(program.clone(), None).unwrap();
         assert_eq!(buffer, program)
     }
 
@@ -721,7 +1518,7 @@ mod tests {
         let program = vec![109,3,21101,4,38,4,99,7];
         let mut expected = hashed_map_fill!(i64, i64, program.clone());
         expected.insert(7, 42);
-        let (memory, _) = computer(program, Some(VecDeque::from(vec![3])));
+        let (memory, _) = computer(program, Some(VecDeque::from(vec![3]))).unwrap();
         assert_eq!(memory, expected);
     }
 
@@ -731,8 +1528,223 @@ mod tests {
         let program = vec![109,3,203,2,99,-1];
         let mut expected = hashed_map_fill!(i64, i64, program.clone());
         expected.insert(5, 42);
-        let (memory, _) = computer(program, Some(VecDeque::from(vec![42])));
+        let (memory, _) = computer(program, Some(VecDeque::from(vec![42]))).unwrap();
         assert_eq!(memory, expected);
     }
-}
 
+    #[test]
+    fn test_errors_are_returned_instead_of_panicking() {
+        assert_eq!(computer(vec![1, 0, 0, 0, 42], None),
+                   Err(IntcodeError::UnknownOpCode { instruction_pointer: 4, opcode: 42 }), "Must report unknown opcode");
+        assert_eq!(computer(vec![3101, 0, 0, 0, 99], None),
+                   Err(IntcodeError::UnknownMode { instruction_pointer: 0, opcode: 3101, parameter: 2 }), "Must report unknown mode");
+        assert_eq!(computer(vec![1101, 1, 1, 5, 3, 0, 99], None),
+                   Err(IntcodeError::MissingInput { instruction_pointer: 4, opcode: 3 }), "Must report missing input");
+        assert_eq!(computer(vec![1, 0, 0, 0, 1105, 1, 10], None),
+                   Err(IntcodeError::OutOfMemory { instruction_pointer: 10 }), "Must report jump out of memory");
+        assert_eq!(computer(vec![1, 0, 0, 0, 5], None),
+                   Err(IntcodeError::MissingParameter { instruction_pointer: 4, opcode: 5, parameter: 1 }), "Must report missing parameter");
+        // neither the immediate operands nor the result address may be missing
+        assert_eq!(computer(vec![1101, 1], None),
+                   Err(IntcodeError::MissingParameter { instruction_pointer: 0, opcode: 1101, parameter: 2 }), "Must report missing operand");
+        let mut truncated = Computer::new(vec![1101, 1, 1]);
+        assert_eq!(truncated.run(), Err(IntcodeError::MissingParameter { instruction_pointer: 0, opcode: 1101, parameter: 3 }));
+        assert_eq!(truncated.memory().cells(), vec![(0, 1101), (1, 1), (2, 1)]);
+    }
+
+    #[test]
+    fn test_computer_awaits_input() {
+        let program = vec![3, 9, 4, 9, 3, 10, 4, 10, 99, -1, -1];
+        let mut computer = Computer::new(program);
+
+        // no input at all
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+        assert!(computer.outputs().is_empty());
+        assert_eq!(computer.instruction_cursor, 0);
+
+        // consumes the input then waits for the next one without ResumeMode
+        computer.add_input(12);
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+        assert_eq!(computer.outputs(), &[12]);
+        assert_eq!(computer.instruction_cursor, 4);
+        assert!(computer.is_setup());
+
+        computer.add_input(42);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[12, 42]);
+    }
+
+    #[test]
+    fn test_peek_and_poke() {
+        // outputs the sum of the cells 20 and 21
+        let mut program = vec![1, 20, 21, 22, 4, 22, 99];
+        program.resize(23, 0);
+        let mut computer = Computer::new(program);
+        computer.set_resume_mode(ResumeMode::Enable);
+        assert_eq!(computer.poke(20, 6), 0);
+        computer.load_at(21, &[7]);
+
+        assert_eq!(computer.run().unwrap(), State::Paused);
+        assert_eq!(computer.peek(22), 13);
+        // patched while paused, the product is output too
+        computer.load_at(6, &[2, 20, 21, 23, 4, 23, 99]);
+        assert_eq!(computer.run().unwrap(), State::Paused);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[13, 42]);
+        assert_eq!(computer.peek_range(20..24), vec![6, 7, 13, 42]);
+        // never written cells read as 0 and stay unwritten
+        assert_eq!(computer.peek(1000), 0);
+        assert_eq!(computer.memory().get(1000), None);
+
+        // values going past the last address are not loaded at all
+        assert!(computer.load_at(i64::MAX - 1, &[1, 2]));
+        assert_eq!(computer.peek_range(i64::MAX - 1..i64::MAX), vec![1]);
+        assert_eq!(computer.peek(i64::MAX), 2);
+        assert!(!computer.load_at(i64::MAX, &[3, 4]));
+        assert_eq!(computer.peek(i64::MAX), 2);
+        assert!(computer.load_at(i64::MAX, &[]));
+    }
+
+    #[test]
+    fn test_run_limits_leave_a_resumable_state() {
+        // counts the input down to 0, then outputs it
+        let countdown = vec![3, 12, 1001, 12, -1, 12, 1005, 12, 2, 4, 12, 99, 0];
+        let mut computer = Computer::new(countdown);
+        computer.add_input(100);
+        computer.set_instruction_budget(Some(50));
+
+        let mut interruptions = 0;
+        while computer.run().unwrap() == State::Interrupted {
+            assert!(computer.is_setup());
+            interruptions += 1;
+        }
+        assert_eq!(interruptions, 4);
+        assert_eq!(computer.state, State::Stopped);
+        assert_eq!(computer.outputs(), &[0]);
+
+        let mut computer = Computer::new(vec![1105, 1, 0]);
+        computer.set_time_limit(Some(Duration::from_millis(20)));
+        assert_eq!(computer.run().unwrap(), State::Interrupted);
+        assert_eq!(computer.instruction_pointer(), 0);
+    }
+
+    #[test]
+    fn test_loop_detection() {
+        let mut computer = Computer::new(vec![1105, 1, 0]);
+        computer.enable_loop_detection();
+        assert_eq!(computer.run(), Err(IntcodeError::InfiniteLoop { instruction_pointer: 0, opcode: 1105 }));
+
+        // the memory changes at each turn, this loop ends
+        let mut computer = Computer::new(vec![3, 12, 1001, 12, -1, 12, 1005, 12, 2, 4, 12, 99, 0]);
+        computer.enable_loop_detection();
+        computer.add_input(10);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+
+        // the hash kept by the detector follows the writes, down to the cell going back to 0
+        let mut computer = Computer::new(vec![3, 12, 1001, 12, -1, 12, 1005, 12, 2, 4, 12, 99, 0]);
+        computer.enable_loop_detection();
+        computer.add_input(2);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.peek(12), 0);
+        assert_eq!(computer.loop_detector.as_ref().unwrap().memory_hash, Some(memory_hash(computer.memory())));
+        assert_eq!(memory_hash(computer.memory()), memory_hash(Computer::new(vec![3, 12, 1001, 12, -1, 12, 1005, 12, 2, 4, 12, 99]).memory()));
+
+        // two states with the same hash are told apart
+        let mut states = StateSet::default();
+        let (first, second) = (Computer::new(vec![99, 1]), Computer::new(vec![99, 2]));
+        assert!(states.insert_hashed(7, &first));
+        assert!(states.insert_hashed(7, &second));
+        assert!(!states.insert_hashed(7, &Computer::new(vec![99, 2])));
+        assert!(states.insert(&first));
+        assert!(!states.insert(&first));
+        let mut detector = LoopDetector::default();
+        assert!(!detector.is_looping(7, &first));
+        assert!(!detector.is_looping(7, &second));
+        assert!(detector.is_looping(7, &second));
+
+        // polls its input, the inputs make each turn different
+        let mut computer = Computer::new(vec![3, 7, 1006, 7, 0, 99, 0, 0]);
+        computer.enable_loop_detection();
+        for _ in 0..3 {
+            computer.add_input(0);
+        }
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+    }
+
+    #[test]
+    fn test_overflow_check() {
+        // squares the input twice then outputs it
+        let program = vec![3, 13, 2, 13, 13, 13, 2, 13, 13, 13, 4, 13, 99, 0];
+
+        let mut computer = Computer::new(program.clone());
+        computer.add_input(1 << 20);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[0]);
+
+        let mut computer = Computer::new(program.clone());
+        computer.enable_overflow_check();
+        computer.add_input(1 << 10);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[1 << 40]);
+
+        let mut computer = Computer::new(program);
+        computer.enable_overflow_check();
+        computer.add_input(1 << 20);
+        assert_eq!(computer.run(), Err(IntcodeError::Overflow { instruction_pointer: 6, opcode: 2 }));
+        assert_eq!(computer.peek(13), 1 << 40);
+
+        let mut computer = Computer::new(vec![109, i64::MAX, 109, 1, 99]);
+        computer.enable_overflow_check();
+        assert_eq!(computer.run(), Err(IntcodeError::Overflow { instruction_pointer: 2, opcode: 109 }));
+
+        // relative addresses past i64::MAX, read then written
+        let mut computer = Computer::new(vec![109, 1, 204, i64::MAX, 99]);
+        computer.enable_profile();
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[0]);
+        let mut computer = Computer::new(vec![109, 1, 204, i64::MAX, 99]);
+        computer.enable_overflow_check();
+        assert_eq!(computer.run(), Err(IntcodeError::Overflow { instruction_pointer: 2, opcode: 204 }));
+        let mut computer = Computer::new(vec![109, 1, 21101, 1, 1, i64::MAX, 99]);
+        computer.enable_overflow_check();
+        assert_eq!(computer.run(), Err(IntcodeError::Overflow { instruction_pointer: 2, opcode: 21101 }));
+        let mut computer = Computer::new(vec![109, 1, 21101, 1, 1, i64::MAX, 99]);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.peek(i64::MIN), 2);
+
+        // the next instruction would be past i64::MAX
+        let mut computer = Computer::new(vec![1105, 1, i64::MAX - 1]);
+        computer.load_at(i64::MAX - 1, &[104, 7]);
+        computer.enable_overflow_check();
+        assert_eq!(computer.run(), Err(IntcodeError::Overflow { instruction_pointer: i64::MAX - 1, opcode: 104 }));
+        assert_eq!(computer.outputs(), &[7]);
+    }
+
+    #[test]
+    fn test_step_describes_each_instruction() {
+        let program = vec![3, 13, 1002, 13, 3, 14, 1005, 14, 10, 99, 4, 14, 99, -1];
+        let mut computer = Computer::new(program);
+        computer.add_input(7);
+
+        let event = computer.step().unwrap();
+        assert_eq!(event.opcode, OpCode::Store);
+        assert_eq!(event.input, Some(7));
+        assert_eq!(event.writes, vec![MemoryWrite { address: 13, old: -1, new: 7 }]);
+        assert_eq!(computer.instruction_cursor, 2);
+
+        let event = computer.step().unwrap();
+        assert_eq!(event.instruction_pointer, 2);
+        assert_eq!(event.raw_opcode, 1002);
+        assert_eq!(event.operands, vec![7, 3]);
+        assert_eq!(event.writes, vec![MemoryWrite { address: 14, old: 0, new: 21 }]);
+
+        let event = computer.step().unwrap();
+        assert_eq!(event.opcode, OpCode::JumpIfTrue);
+        assert_eq!(event.jump, Some(10));
+        assert_eq!(computer.instruction_cursor, 10);
+
+        let event = computer.step().unwrap();
+        assert_eq!(event.output, Some(21));
+        assert_eq!(computer.state, State::Started);
+    }
+}
@@ -0,0 +1,255 @@
+//!
+//! Code coverage of a program, computed from the profiles of one or more runs
+//!
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::computer::OpCode;
+use crate::disasm::{decode_with, Line};
+use crate::profile::Profile;
+use crate::registry::InstructionSet;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum CellKind {
+    /// An instruction started here
+    Code,
+    /// Parameter of an executed instruction
+    Operand,
+    /// Never executed but read or written by an instruction
+    Data,
+    /// Neither executed nor accessed
+    Unused
+}
+
+impl CellKind {
+    fn name(self) -> &'static str {
+        match self {
+            CellKind::Code => "code",
+            CellKind::Operand => "operand",
+            CellKind::Data => "data",
+            CellKind::Unused => "unused"
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct CellCoverage {
+    pub address: i64,
+    pub kind: CellKind,
+    pub executions: u64,
+    pub reads: u64,
+    pub writes: u64
+}
+
+/// How an executed conditional jump behaved
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Branch {
+    pub address: i64,
+    pub taken: u64,
+    pub not_taken: u64
+}
+
+impl Branch {
+    pub fn is_covered(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Coverage {
+    /// One entry per cell of the program
+    pub cells: Vec<CellCoverage>,
+    /// Conditional jumps executed at least once, by address
+    pub branches: Vec<Branch>,
+    program: Vec<i64>,
+    /// The instruction starting at each address, if any
+    lines: Vec<Option<Line>>
+}
+
+impl Coverage {
+    /// Coverage of `program` as loaded, `profile` being recorded over one or more runs of it
+    pub fn new(program: &[i64], profile: &Profile) -> Coverage {
+        Coverage::with_instruction_set(program, profile, &InstructionSet::standard())
+    }
+
+    /// Coverage of a program run with the opcodes of the set, see `new`
+    pub fn with_instruction_set(program: &[i64], profile: &Profile, instructions: &InstructionSet) -> Coverage {
+        let count = |counts: &HashMap<i64, u64>, address: i64| *counts.get(&address).unwrap_or(&0);
+        let fetch = |address: i64| program.get(address as usize).copied();
+        let lines: Vec<Option<Line>> = (0..program.len() as i64).map(|address| decode_with(fetch, address, instructions)).collect();
+
+        let mut cells: Vec<CellCoverage> = (0..program.len() as i64).map(|address| CellCoverage {
+            address,
+            kind: CellKind::Unused,
+            executions: count(&profile.addresses, address),
+            reads: count(&profile.reads, address),
+            writes: count(&profile.writes, address)
+        }).collect();
+
+        let mut branches = vec![];
+        for address in 0..program.len() as i64 {
+            let executions = cells[address as usize].executions;
+            if executions == 0 {
+                continue;
+            }
+            cells[address as usize].kind = CellKind::Code;
+            if let Some(Line::Instruction { opcode, parameters, .. }) = &lines[address as usize] {
+                for operand in address + 1..=address + parameters.len() as i64 {
+                    if let Some(cell) = cells.get_mut(operand as usize).filter(|cell| cell.kind == CellKind::Unused) {
+                        cell.kind = CellKind::Operand;
+                    }
+                }
+                if *opcode == OpCode::JumpIfTrue || *opcode == OpCode::JumpIfFalse {
+                    let taken: u64 = profile.jumps.iter()
+                        .filter(|((from, _), _)| *from == address)
+                        .map(|(_, count)| count)
+                        .sum();
+                    branches.push(Branch { address, taken, not_taken: executions - taken });
+                }
+            }
+        }
+        for cell in cells.iter_mut() {
+            if cell.kind == CellKind::Unused && cell.reads + cell.writes > 0 {
+                cell.kind = CellKind::Data;
+            }
+        }
+
+        Coverage { cells, branches, program: program.to_vec(), lines }
+    }
+
+    /// Number of cells of each kind, in the order code, operand, data, unused
+    pub fn totals(&self) -> [usize; 4] {
+        let mut totals = [0; 4];
+        for cell in self.cells.iter() {
+            totals[cell.kind as usize] += 1;
+        }
+        totals
+    }
+
+    /// Branches which always or never jumped
+    pub fn uncovered_branches(&self) -> Vec<&Branch> {
+        self.branches.iter().filter(|branch| !branch.is_covered()).collect()
+    }
+
+    ///
+    /// Disassembly of the program with the number of executions in front of each instruction,
+    /// `-` for the instructions which never ran, and the branches outcome in comments.
+    /// A cell neither executed nor used as data is shown as an instruction when it decodes as one.
+    ///
+    pub fn annotated_listing(&self) -> String {
+        let mut lines = vec![];
+        let mut address = 0;
+        while address < self.cells.len() {
+            let cell = &self.cells[address];
+            let instruction = match cell.kind {
+                CellKind::Code => self.lines[address].clone(),
+                // Only if the whole instruction is unused, it would overlap code or data otherwise
+                CellKind::Unused => self.lines[address].clone().filter(|line| {
+                    self.cells[address..].iter().take(line.cell_count()).all(|cell| cell.kind == CellKind::Unused)
+                }),
+                _ => None
+            };
+
+            let line = match instruction {
+                Some(line) => {
+                    let marker = match cell.kind {
+                        CellKind::Code => cell.executions.to_string(),
+                        _ => "-".to_string()
+                    };
+                    let mut text = format!("{:>10} | {}", marker, line);
+                    if let Some(branch) = self.branches.iter().find(|branch| branch.address == address as i64) {
+                        write!(text, "    ; taken {}, not taken {}", branch.taken, branch.not_taken).unwrap();
+                    }
+                    address += line.cell_count();
+                    text
+                },
+                None => {
+                    let marker = match cell.kind {
+                        CellKind::Data | CellKind::Operand => format!("r{} w{}", cell.reads, cell.writes),
+                        _ => "-".to_string()
+                    };
+                    let line = Line::Data { address: address as i64, value: self.program[address] };
+                    address += 1;
+                    format!("{:>10} | {}", marker, line)
+                }
+            };
+            lines.push(line);
+        }
+        lines.join("\n")
+    }
+
+    ///
+    /// CSV summary, one row per cell: `address,kind,executions,reads,writes,taken,not_taken`,
+    /// the last two columns being empty for anything but an executed conditional jump
+    ///
+    pub fn summary(&self) -> String {
+        let mut summary = String::from("address,kind,executions,reads,writes,taken,not_taken\n");
+        for cell in self.cells.iter() {
+            let branch = match self.branches.iter().find(|branch| branch.address == cell.address) {
+                Some(branch) => format!("{},{}", branch.taken, branch.not_taken),
+                None => ",".to_string()
+            };
+            writeln!(summary, "{},{},{},{},{},{}", cell.address, cell.kind.name(), cell.executions, cell.reads, cell.writes, branch).unwrap();
+        }
+        summary
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::coverage::{Coverage, CellKind, Branch};
+    use crate::computer::Computer;
+    use crate::profile::Profile;
+
+    /// Outputs 1 if the input is 8, 0 otherwise
+    const IS_EIGHT: [i64; 17] = [3, 15, 1008, 15, 8, 16, 1005, 16, 12, 104, 0, 99, 104, 1, 99, 0, 0];
+
+    fn profile(inputs: &[i64]) -> Profile {
+        let mut profile = Profile::default();
+        for input in inputs {
+            let mut computer = Computer::new(IS_EIGHT.to_vec());
+            computer.enable_profile();
+            computer.add_input(*input);
+            computer.run().unwrap();
+            profile.merge(computer.profile().unwrap());
+        }
+        profile
+    }
+
+    #[test]
+    fn test_coverage_of_one_branch() {
+        let coverage = Coverage::new(&IS_EIGHT, &profile(&[3]));
+        assert_eq!(coverage.branches, vec![Branch { address: 6, taken: 0, not_taken: 1 }]);
+        assert_eq!(coverage.uncovered_branches().len(), 1);
+        assert_eq!(coverage.cells[7].kind, CellKind::Operand);
+        assert_eq!(coverage.cells[15].kind, CellKind::Data);
+        // the end of the program after the jump never ran
+        assert_eq!(coverage.totals(), [5, 7, 2, 3]);
+
+        let expected = [
+            "         1 |     0: in [15]",
+            "         1 |     2: eq [15], #8, [16]",
+            "         1 |     6: jt [16], #12    ; taken 0, not taken 1",
+            "         1 |     9: out #0",
+            "         1 |    11: hlt",
+            "         - |    12: out #1",
+            "         - |    14: hlt",
+            "     r1 w1 |    15: data 0",
+            "     r1 w1 |    16: data 0"
+        ].join("\n");
+        assert_eq!(coverage.annotated_listing(), expected);
+    }
+
+    #[test]
+    fn test_merged_runs_cover_both_branches() {
+        let coverage = Coverage::new(&IS_EIGHT, &profile(&[3, 8]));
+        assert_eq!(coverage.branches, vec![Branch { address: 6, taken: 1, not_taken: 1 }]);
+        assert!(coverage.uncovered_branches().is_empty());
+
+        let summary = coverage.summary();
+        let rows: Vec<&str> = summary.lines().collect();
+        assert_eq!(rows[0], "address,kind,executions,reads,writes,taken,not_taken");
+        assert_eq!(rows[7], "6,code,2,0,0,1,1");
+        assert_eq!(rows[13], "12,code,1,0,0,,");
+        assert_eq!(rows[16], "15,data,0,2,2,,");
+    }
+}
@@ -0,0 +1,364 @@
+use std::collections::HashSet;
+use crate::computer::{Computer, OpCode, State, StepEvent, MemoryWrite, IntcodeError};
+use crate::disasm::{decode_with, Line};
+
+/// Instructions the debugger can step back over
+const HISTORY_LIMIT: usize = 1_000_000;
+
+/// Why the execution gave the control back to the user
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum StopReason {
+    /// The next instruction is at a breakpoint address
+    Breakpoint(i64),
+    /// The next instruction has a watched opcode
+    OpCodeBreakpoint(OpCode),
+    /// A watched memory cell has just been written
+    Watchpoint(MemoryWrite),
+    AwaitingInput,
+    Halted,
+    Error(IntcodeError)
+}
+
+///
+/// Drives a computer one instruction at a time, stopping on breakpoints and watchpoints
+///
+#[derive(Debug)]
+pub struct Debugger {
+    pub computer: Computer,
+    breakpoints: HashSet<i64>,
+    opcode_breakpoints: HashSet<OpCode>,
+    watchpoints: HashSet<i64>
+}
+
+impl Debugger {
+    pub fn new(program: Vec<i64>) -> Debugger {
+        let mut computer = Computer::new(program);
+        computer.enable_history(Some(HISTORY_LIMIT));
+        Debugger {
+            computer,
+            breakpoints: HashSet::new(),
+            opcode_breakpoints: HashSet::new(),
+            watchpoints: HashSet::new()
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: i64) {
+        self.breakpoints.insert(address);
+    }
+
+    pub fn add_opcode_breakpoint(&mut self, opcode: OpCode) {
+        self.opcode_breakpoints.insert(opcode);
+    }
+
+    pub fn add_watchpoint(&mut self, address: i64) {
+        self.watchpoints.insert(address);
+    }
+
+    /// The instruction under the cursor
+    pub fn current_line(&self) -> Line {
+        let address = self.computer.instruction_pointer();
+        match decode_with(|x| self.computer.memory().get(x), address, self.computer.instruction_set()) {
+            Some(line) => line,
+            None => Line::Data { address, value: self.computer.peek(address) }
+        }
+    }
+
+    /// Executes one instruction, `None` if the computer can't go further
+    pub fn step(&mut self) -> Result<Option<StepEvent>, StopReason> {
+        if self.computer.state == State::Stopped {
+            return Err(StopReason::Halted)
+        }
+        let event = self.computer.step().map_err(StopReason::Error)?;
+        match self.computer.state {
+            State::AwaitingInput => Ok(None),
+            _ => Ok(Some(event))
+        }
+    }
+
+    /// Runs until a breakpoint, a watchpoint, the end of the program or an error
+    pub fn resume(&mut self) -> StopReason {
+        let mut first = true;
+        loop {
+            let address = self.computer.instruction_pointer();
+            if !first {
+                if self.breakpoints.contains(&address) {
+                    return StopReason::Breakpoint(address)
+                }
+                if let Line::Instruction { opcode, .. } = self.current_line() {
+                    if self.opcode_breakpoints.contains(&opcode) {
+                        return StopReason::OpCodeBreakpoint(opcode)
+                    }
+                }
+            }
+            first = false;
+
+            let event = match self.step() {
+                Ok(Some(event)) => event,
+                Ok(None) => return StopReason::AwaitingInput,
+                Err(reason) => return reason
+            };
+            if let Some(write) = event.writes.iter().find(|x| self.watchpoints.contains(&x.address)) {
+                return StopReason::Watchpoint(*write)
+            }
+            if self.computer.state == State::Stopped {
+                return StopReason::Halted
+            }
+        }
+    }
+
+    fn describe_stop(&self, reason: &StopReason) -> String {
+        let reason = match reason {
+            StopReason::Breakpoint(address) => format!("breakpoint at {}", address),
+            StopReason::OpCodeBreakpoint(opcode) => format!("breakpoint on {}", self.computer.instruction_set().mnemonic(*opcode)),
+            StopReason::Watchpoint(write) => format!("watchpoint [{}]: {} -> {}", write.address, write.old, write.new),
+            StopReason::AwaitingInput => "waiting for input".to_string(),
+            StopReason::Halted => "program halted".to_string(),
+            StopReason::Error(error) => format!("error {}", error)
+        };
+        // the cursor is past the end of the program once halted
+        match self.computer.state {
+            State::Stopped => reason,
+            _ => format!("{}\n{}", reason, self.current_line())
+        }
+    }
+
+    fn describe_event(&self, event: &StepEvent) -> String {
+        let mut text = vec![];
+        for write in event.writes.iter() {
+            text.push(format!("[{}]: {} -> {}", write.address, write.old, write.new));
+        }
+        if let Some(output) = event.output {
+            text.push(format!("output: {}", output));
+        }
+        if let Some(jump) = event.jump {
+            text.push(format!("jump to {}", jump));
+        }
+        match self.computer.state {
+            State::Stopped => text.push("program halted".to_string()),
+            _ => text.push(self.current_line().to_string())
+        }
+        text.join("\n")
+    }
+
+    ///
+    /// Runs a command typed by the user and returns what to print
+    ///
+    pub fn execute(&mut self, command: &str) -> String {
+        let words: Vec<&str> = command.split_whitespace().collect();
+        let numbers: Result<Vec<i64>, _> = words.iter().skip(1).map(|x| x.parse::<i64>()).collect();
+        let numbers = match numbers {
+            Ok(numbers) => numbers,
+            Err(_) if words.len() == 2 && matches!(words[0], "b" | "break" | "d" | "delete") => vec![],
+            Err(_) => return format!("invalid number in: {}", command)
+        };
+
+        match (words.first().copied(), &numbers[..]) {
+            (None, _) => String::new(),
+            (Some("s"), []) | (Some("step"), []) => self.execute("step 1"),
+            (Some("s"), [count]) | (Some("step"), [count]) => {
+                let mut text = vec![];
+                for _ in 0..*count {
+                    match self.step() {
+                        Ok(Some(event)) => text.push(self.describe_event(&event)),
+                        Ok(None) => {
+                            text.push(self.describe_stop(&StopReason::AwaitingInput));
+                            break
+                        },
+                        Err(StopReason::Halted) => {
+                            // already reported by the event of the halt instruction
+                            if text.is_empty() {
+                                text.push(self.describe_stop(&StopReason::Halted));
+                            }
+                            break
+                        },
+                        Err(reason) => {
+                            text.push(self.describe_stop(&reason));
+                            break
+                        }
+                    }
+                }
+                text.join("\n")
+            },
+            (Some("back"), []) => self.execute("back 1"),
+            (Some("back"), [count]) => {
+                let undone = (0..*count).take_while(|_| self.computer.step_back().is_some()).count();
+                format!("{} instruction(s) undone\n{}", undone, self.current_line())
+            },
+            (Some("last"), [address]) => {
+                match self.computer.rewind_to_write(*address) {
+                    Some(entry) => {
+                        let write = entry.instruction.writes.iter().find(|write| write.address == *address).unwrap();
+                        format!("[{}]: {} -> {}\n{}", write.address, write.old, write.new, self.current_line())
+                    },
+                    None => format!("no write to [{}] in the history", address)
+                }
+            },
+            (Some("c"), []) | (Some("continue"), []) => {
+                let reason = self.resume();
+                self.describe_stop(&reason)
+            },
+            (Some("b"), _) | (Some("break"), _) | (Some("d"), _) | (Some("delete"), _) if words.len() == 2 => {
+                let add = matches!(words[0], "b" | "break");
+                let opcode = self.computer.instruction_set().definition_by_mnemonic(words[1]).map(|definition| definition.opcode);
+                match (numbers.first(), opcode) {
+                    (Some(address), _) if add => { self.add_breakpoint(*address); format!("breakpoint at {}", address) },
+                    (Some(address), _) => { self.breakpoints.remove(address); format!("breakpoint at {} deleted", address) },
+                    (None, Some(opcode)) if add => { self.add_opcode_breakpoint(opcode); format!("breakpoint on {}", words[1]) },
+                    (None, Some(opcode)) => { self.opcode_breakpoints.remove(&opcode); format!("breakpoint on {} deleted", words[1]) },
+                    (None, None) => format!("unknown mnemonic {}", words[1])
+                }
+            },
+            (Some("w"), [address]) | (Some("watch"), [address]) => {
+                self.add_watchpoint(*address);
+                format!("watching [{}]", address)
+            },
+            (Some("unwatch"), [address]) => {
+                self.watchpoints.remove(address);
+                format!("[{}] no longer watched", address)
+            },
+            (Some("x"), [address]) => self.execute(&format!("x {} 1", address)),
+            (Some("x"), [_, count]) if *count <= 0 => format!("invalid count: {}", count),
+            (Some("x"), [address, count]) => match address.checked_add(*count - 1) {
+                Some(last) => (*address..=last)
+                    .map(|x| format!("[{}] = {}", x, self.computer.peek(x)))
+                    .collect::<Vec<String>>()
+                    .join("\n"),
+                None => format!("addresses past {} don't fit in 64 bits", i64::MAX)
+            },
+            (Some("set"), [address, value]) => {
+                let old = self.computer.poke(*address, *value);
+                format!("[{}]: {} -> {}", address, old, value)
+            },
+            (Some("base"), []) => format!("base = {}", self.computer.base()),
+            (Some("base"), [base]) => {
+                // like `poke`, the history can't undo the change
+                self.computer.set_base(*base);
+                self.computer.clear_history();
+                format!("base = {}", base)
+            },
+            (Some("input"), values) if !values.is_empty() => {
+                for value in values {
+                    self.computer.add_input(*value);
+                }
+                format!("{} value(s) queued", values.len())
+            },
+            (Some("l"), []) | (Some("list"), []) => self.current_line().to_string(),
+            (Some("i"), []) | (Some("info"), []) => {
+                let mut breakpoints = self.breakpoints.iter().map(|x| x.to_string()).collect::<Vec<String>>();
+                let instructions = self.computer.instruction_set();
+                breakpoints.extend(self.opcode_breakpoints.iter().map(|x| instructions.mnemonic(*x).to_string()));
+                breakpoints.sort();
+                let mut watchpoints = self.watchpoints.iter().copied().collect::<Vec<i64>>();
+                watchpoints.sort();
+                format!("ip = {}\nbase = {}\nstate = {:?}\nbreakpoints = {:?}\nwatchpoints = {:?}\noutputs = {:?}",
+                        self.computer.instruction_pointer(), self.computer.base(), self.computer.state,
+                        breakpoints, watchpoints, self.computer.outputs())
+            },
+            (Some("h"), []) | (Some("help"), []) => HELP.to_string(),
+            _ => format!("unknown command: {}, type help", command)
+        }
+    }
+}
+
+const HELP: &str = "\
+s, step [n]          execute n instructions (default 1)
+c, continue          run until a breakpoint, a watchpoint or the end
+back [n]             undo n instructions (default 1)
+last <addr>          go back to the last write to the memory cell
+b, break <addr|op>   break at an address or on a mnemonic (add, mul, in...)
+d, delete <addr|op>  remove a breakpoint
+w, watch <addr>      stop after a write to the memory cell
+unwatch <addr>       remove a watchpoint
+x <addr> [count]     print memory cells
+set <addr> <value>   change a memory cell, forgetting the history
+base [value]         print or change the relative base, a change forgets the history
+input <v>...         queue input values
+l, list              show the current instruction
+i, info              show registers, breakpoints and outputs
+q, quit              leave the debugger";
+
+#[cfg(test)]
+mod tests {
+    use super::{Debugger, StopReason};
+    use crate::computer::{OpCode, MemoryWrite, State};
+
+    fn debugger() -> Debugger {
+        // counts down from the input
+        Debugger::new(vec![3,13,4,13,1001,13,-1,13,1005,13,2,99,0,0])
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut debugger = debugger();
+        debugger.computer.add_input(3);
+        debugger.add_breakpoint(8);
+        assert_eq!(debugger.resume(), StopReason::Breakpoint(8));
+        assert_eq!(debugger.computer.instruction_pointer(), 8);
+        // the breakpoint under the cursor doesn't stop the next resume
+        assert_eq!(debugger.resume(), StopReason::Breakpoint(8));
+
+        let mut debugger = super::tests::debugger();
+        debugger.computer.add_input(3);
+        debugger.add_opcode_breakpoint(OpCode::Output);
+        assert_eq!(debugger.resume(), StopReason::OpCodeBreakpoint(OpCode::Output));
+        assert_eq!(debugger.computer.instruction_pointer(), 2);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut debugger = debugger();
+        debugger.add_watchpoint(13);
+        assert_eq!(debugger.resume(), StopReason::AwaitingInput);
+        debugger.computer.add_input(2);
+        assert_eq!(debugger.resume(), StopReason::Watchpoint(MemoryWrite { address: 13, old: 0, new: 2 }));
+        assert_eq!(debugger.resume(), StopReason::Watchpoint(MemoryWrite { address: 13, old: 2, new: 1 }));
+        assert_eq!(debugger.resume(), StopReason::Watchpoint(MemoryWrite { address: 13, old: 1, new: 0 }));
+        assert_eq!(debugger.resume(), StopReason::Halted);
+        assert_eq!(debugger.computer.state, State::Stopped);
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut debugger = debugger();
+        assert_eq!(debugger.execute("list"), "    0: in [13]");
+        assert_eq!(debugger.execute("input 2"), "1 value(s) queued");
+        assert_eq!(debugger.execute("step"), "[13]: 0 -> 2\n    2: out [13]");
+        assert_eq!(debugger.execute("s"), "output: 2\n    4: add [13], #-1, [13]");
+        assert_eq!(debugger.execute("set 13 5"), "[13]: 2 -> 5");
+        assert_eq!(debugger.execute("x 12 2"), "[12] = 0\n[13] = 5");
+        assert_eq!(debugger.execute("base 7"), "base = 7");
+        // the changes made by hand can't be undone
+        assert_eq!(debugger.execute("back"), "0 instruction(s) undone\n    4: add [13], #-1, [13]");
+        assert_eq!(debugger.execute("b jt"), "breakpoint on jt");
+        assert_eq!(debugger.execute("c"), "breakpoint on jt\n    8: jt [13], #2");
+        assert_eq!(debugger.execute("d jt"), "breakpoint on jt deleted");
+        assert_eq!(debugger.execute("b 4"), "breakpoint at 4");
+        assert_eq!(debugger.execute("c"), "breakpoint at 4\n    4: add [13], #-1, [13]");
+        assert_eq!(debugger.execute("info"), "ip = 4\nbase = 7\nstate = Started\nbreakpoints = [\"4\"]\nwatchpoints = []\noutputs = [2, 4]");
+        assert_eq!(debugger.execute("x foo"), "invalid number in: x foo");
+        assert_eq!(debugger.execute("x 12 0"), "invalid count: 0");
+        assert_eq!(debugger.execute("x 12 -1"), "invalid count: -1");
+        assert_eq!(debugger.execute("x 9223372036854775807 1"), "[9223372036854775807] = 0");
+        assert_eq!(debugger.execute("x 9223372036854775807 2"), "addresses past 9223372036854775807 don't fit in 64 bits");
+        assert_eq!(debugger.execute("delete 4"), "breakpoint at 4 deleted");
+        assert_eq!(debugger.execute("step 3"), "[13]: 4 -> 3\n    8: jt [13], #2\njump to 2\n    2: out [13]\noutput: 3\n    4: add [13], #-1, [13]");
+        assert_eq!(debugger.execute("c"), "program halted");
+        assert_eq!(debugger.execute("s"), "program halted");
+    }
+
+    #[test]
+    fn test_stepping_back() {
+        let mut debugger = debugger();
+        debugger.computer.add_input(2);
+        assert_eq!(debugger.resume(), StopReason::Halted);
+        assert_eq!(debugger.execute("back 2"), "2 instruction(s) undone\n    8: jt [13], #2");
+        assert_eq!(debugger.execute("last 13"), "[13]: 1 -> 0\n    4: add [13], #-1, [13]");
+        assert_eq!(debugger.execute("back"), "1 instruction(s) undone\n    2: out [13]");
+        assert_eq!(debugger.execute("info"), "ip = 2\nbase = 0\nstate = Started\nbreakpoints = []\nwatchpoints = []\noutputs = [2]");
+        assert_eq!(debugger.execute("last 12"), "no write to [12] in the history");
+        assert_eq!(debugger.execute("back 100"), "4 instruction(s) undone\n    0: in [13]");
+        assert_eq!(debugger.computer.peek(13), 0);
+        assert_eq!(debugger.resume(), StopReason::Halted);
+        assert_eq!(debugger.execute("info").lines().last(), Some("outputs = [2, 1]"));
+    }
+}
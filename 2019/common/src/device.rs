@@ -0,0 +1,227 @@
+//!
+//! Virtual devices mapped on a range of addresses, see `Computer::attach_device`.
+//!
+//! The operands read and the results written by the instructions in that range go to the device
+//! instead of the memory. Fetching the instructions and their parameters, `peek`, `poke` and `load_at`
+//! still see the memory underneath. The accesses are reported in `StepEvent::device_accesses`
+//! rather than as memory writes.
+//!
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+pub trait Device: Send {
+    /// Value read by an instruction, `offset` being counted from the start of the mapped range
+    fn read(&mut self, offset: i64) -> i64;
+
+    fn write(&mut self, offset: i64, value: i64);
+}
+
+impl fmt::Debug for dyn Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Device")
+    }
+}
+
+/// Device reading and writing through closures
+pub struct DeviceFn<R, W> {
+    pub read: R,
+    pub write: W
+}
+
+impl<R: FnMut(i64) -> i64 + Send, W: FnMut(i64, i64) + Send> Device for DeviceFn<R, W> {
+    fn read(&mut self, offset: i64) -> i64 {
+        (self.read)(offset)
+    }
+
+    fn write(&mut self, offset: i64, value: i64) {
+        (self.write)(offset, value)
+    }
+}
+
+/// Milliseconds elapsed since the clock was created, writes are ignored
+#[derive(Debug, Clone, Copy)]
+pub struct Clock {
+    start: Instant
+}
+
+impl Clock {
+    pub fn new() -> Clock {
+        Clock { start: Instant::now() }
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Clock {
+        Clock::new()
+    }
+}
+
+impl Device for Clock {
+    fn read(&mut self, _offset: i64) -> i64 {
+        self.start.elapsed().as_millis() as i64
+    }
+
+    fn write(&mut self, _offset: i64, _value: i64) {}
+}
+
+/// Pseudo-random non-negative values, writing a value seeds the generator again
+#[derive(Debug, Clone, Copy)]
+pub struct Random {
+    state: u64
+}
+
+impl Random {
+    pub fn new(seed: i64) -> Random {
+        let mut random = Random { state: 0 };
+        random.write(0, seed);
+        random
+    }
+}
+
+impl Device for Random {
+    // xorshift64*
+    fn read(&mut self, _offset: i64) -> i64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 1) as i64
+    }
+
+    fn write(&mut self, _offset: i64, value: i64) {
+        // a zero state would only give zeros
+        self.state = (value as u64).max(1);
+    }
+}
+
+///
+/// Grid of pixels, one cell per pixel row after row. Clones share the pixels, so a clone kept
+/// by the caller shows what the program draws.
+///
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    width: usize,
+    pixels: Arc<Mutex<Vec<i64>>>
+}
+
+impl Framebuffer {
+    /// Maps on `width * height` cells
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer { width, pixels: Arc::new(Mutex::new(vec![0; width * height])) }
+    }
+
+    pub fn pixels(&self) -> Vec<i64> {
+        self.pixels.lock().unwrap().clone()
+    }
+
+    /// One line per row, 0 being drawn `.`, 1 `#` and anything else `?`
+    pub fn render(&self) -> String {
+        let pixels = self.pixels.lock().unwrap();
+        pixels.chunks(self.width.max(1))
+            .map(|row| row.iter().map(|pixel| match pixel {
+                0 => '.',
+                1 => '#',
+                _ => '?'
+            }).collect::<String>())
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: i64) -> i64 {
+        self.pixels.lock().unwrap().get(offset as usize).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, offset: i64, value: i64) {
+        if let Some(pixel) = self.pixels.lock().unwrap().get_mut(offset as usize) {
+            *pixel = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::device::{Device, DeviceFn, Framebuffer, Random, Clock};
+    use crate::computer::{Computer, DeviceAccess, IntcodeError, State};
+    use crate::asm::assemble;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_devices_intercept_operands_and_results() {
+        // doubles the value of the device at 1000 into the device at 1001
+        let program = assemble("
+                mul [1000], #2, [1001]
+                add [1000], #0, [x]
+                out [x]
+                hlt
+        x:      data 0").unwrap();
+        let written = Arc::new(Mutex::new(vec![]));
+        let log = written.clone();
+
+        let mut computer = Computer::new(program);
+        let attached = computer.attach_device(1000..1002, DeviceFn {
+            read: |offset| 20 + offset,
+            write: move |offset, value| log.lock().unwrap().push((offset, value))
+        });
+        assert!(attached);
+        assert!(!computer.attach_device(1001..1005, Clock::new()));
+
+        let event = computer.step().unwrap();
+        assert_eq!(event.device_accesses, vec![
+            DeviceAccess::Read { address: 1000, value: 20 },
+            DeviceAccess::Write { address: 1001, value: 40 }
+        ]);
+        assert!(event.writes.is_empty());
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(*written.lock().unwrap(), vec![(1, 40)]);
+        assert_eq!(computer.outputs(), &[20]);
+        // the memory under the device is untouched
+        assert_eq!(computer.peek(1001), 0);
+        assert_eq!(computer.memory().get(1000), None);
+    }
+
+    #[test]
+    fn test_busy_wait_on_the_clock() {
+        // waits for 20 ms to elapse, every turn of the loop is in the same state but for the clock
+        let program = assemble("
+        wait:   lt [1000], #20, [busy]
+                jt [busy], #wait
+                out #1
+                hlt
+        busy:   data 0").unwrap();
+
+        let mut computer = Computer::new(program.clone());
+        computer.attach_device(1000..1001, Clock::new());
+        computer.enable_loop_detection();
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[1]);
+
+        // the same loop on a plain memory cell never ends
+        let mut computer = Computer::new(program);
+        computer.enable_loop_detection();
+        assert_eq!(computer.run(), Err(IntcodeError::InfiniteLoop { instruction_pointer: 4, opcode: 1005 }));
+    }
+
+    #[test]
+    fn test_framebuffer_and_random() {
+        // draws a diagonal on a 3x3 screen mapped at 100
+        let program = assemble("
+                add #1, #0, [100]
+                add #1, #0, [104]
+                add #1, #0, [108]
+                hlt").unwrap();
+        let screen = Framebuffer::new(3, 3);
+        let mut computer = Computer::new(program);
+        computer.attach_device(100..109, screen.clone());
+        computer.run().unwrap();
+        assert_eq!(screen.render(), "#..\n.#.\n..#");
+
+        let mut random = Random::new(42);
+        let values: Vec<i64> = (0..3).map(|_| random.read(0)).collect();
+        assert!(values.iter().all(|value| *value >= 0));
+        assert_ne!(values[0], values[1]);
+        random.write(0, 42);
+        assert_eq!(random.read(0), values[0]);
+    }
+}
@@ -0,0 +1,160 @@
+use std::fmt;
+use crate::computer::{OpCode, Mode};
+use crate::registry::InstructionSet;
+
+/// One line of a listing: either a decoded instruction or a raw value
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Line {
+    Instruction {
+        address: i64,
+        opcode: OpCode,
+        mnemonic: &'static str,
+        parameters: Vec<(Mode, i64)>
+    },
+    Data {
+        address: i64,
+        value: i64
+    }
+}
+
+impl Line {
+    pub fn address(&self) -> i64 {
+        match self {
+            Line::Instruction { address, .. } | Line::Data { address, .. } => *address
+        }
+    }
+
+    /// Number of memory cells covered by the line
+    pub fn cell_count(&self) -> usize {
+        match self {
+            Line::Instruction { parameters, .. } => parameters.len() + 1,
+            Line::Data { .. } => 1
+        }
+    }
+}
+
+/// Renders a parameter as `[x]` (positional), `#x` (immediate) or `[rb+x]` (relative)
+pub fn format_parameter(mode: Mode, value: i64) -> String {
+    match mode {
+        Mode::Positional => format!("[{}]", value),
+        Mode::Immediate => format!("#{}", value),
+        Mode::Relative if value < 0 => format!("[rb{}]", value),
+        Mode::Relative => format!("[rb+{}]", value)
+    }
+}
+
+impl fmt::Display for Line {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Line::Instruction { address, mnemonic, parameters, .. } => {
+                let parameters = parameters
+                    .iter()
+                    .map(|(mode, value)| format_parameter(*mode, *value))
+                    .collect::<Vec<String>>();
+                if parameters.is_empty() {
+                    write!(f, "{:>5}: {}", address, mnemonic)
+                } else {
+                    write!(f, "{:>5}: {} {}", address, mnemonic, parameters.join(", "))
+                }
+            },
+            Line::Data { address, value } => write!(f, "{:>5}: data {}", address, value)
+        }
+    }
+}
+
+/// Decodes the instruction at `address`, `fetch` gives the content of a memory cell.
+/// Fails if the value isn't an instruction or if one of its parameters can't be fetched.
+/// Also fails if the value has digits the assembler doesn't write, such as the mode of a parameter
+/// the instruction doesn't have, so that assembling the listing gives the program back.
+pub fn decode<F>(fetch: F, address: i64) -> Option<Line> where F: Fn(i64) -> Option<i64> {
+    decode_with(fetch, address, &InstructionSet::standard())
+}
+
+/// Decodes the instruction at `address` with the opcodes of the set, see `decode`
+pub fn decode_with<F>(fetch: F, address: i64, instructions: &InstructionSet) -> Option<Line> where F: Fn(i64) -> Option<i64> {
+    let raw = fetch(address)?;
+    let (parameters_mode, opcode) = instructions.decode(raw, address).ok()?;
+    let modes = &parameters_mode.modes()[..instructions.parameter_count(opcode)];
+    if opcode.encode(modes) != raw {
+        return None
+    }
+
+    let parameters = modes
+        .iter()
+        .enumerate()
+        .map(|(offset, mode)| fetch(address + offset as i64 + 1).map(|value| (*mode, value)))
+        .collect::<Option<Vec<(Mode, i64)>>>()?;
+
+    Some(Line::Instruction { address, opcode, mnemonic: instructions.mnemonic(opcode), parameters })
+}
+
+///
+/// Sweeps the program from the first address, anything that doesn't decode as an instruction
+/// is kept as a single data cell
+///
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    disassemble_with(program, &InstructionSet::standard())
+}
+
+/// Sweeps the program with the opcodes of the set, see `disassemble`
+pub fn disassemble_with(program: &[i64], instructions: &InstructionSet) -> Vec<Line> {
+    let mut lines = vec![];
+    let mut address = 0;
+    while address < program.len() {
+        let fetch = |x: i64| program.get(x as usize).copied();
+        let line = match decode_with(fetch, address as i64, instructions) {
+            Some(line) => line,
+            None => Line::Data { address: address as i64, value: program[address] }
+        };
+        address += line.cell_count();
+        lines.push(line);
+    }
+    lines
+}
+
+/// The whole listing, one line per instruction
+pub fn listing(program: &[i64]) -> String {
+    disassemble(program)
+        .iter()
+        .map(|line| line.to_string())
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{disassemble, listing, Line};
+    use crate::computer::{OpCode, Mode, read_program_file};
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_disassemble() {
+        let program = vec![3,9,8,9,10,9,4,9,99,-1,8];
+        assert_eq!(disassemble(&program), vec![
+            Line::Instruction { address: 0, opcode: OpCode::Store, mnemonic: "in", parameters: vec![(Mode::Positional, 9)] },
+            Line::Instruction { address: 2, opcode: OpCode::Equals, mnemonic: "eq", parameters: vec![(Mode::Positional, 9), (Mode::Positional, 10), (Mode::Positional, 9)] },
+            Line::Instruction { address: 6, opcode: OpCode::Output, mnemonic: "out", parameters: vec![(Mode::Positional, 9)] },
+            Line::Instruction { address: 8, opcode: OpCode::Stop, mnemonic: "hlt", parameters: vec![] },
+            Line::Data { address: 9, value: -1 },
+            Line::Data { address: 10, value: 8 },
+        ]);
+    }
+
+    #[test]
+    fn test_listing() {
+        let program = vec![109,3,21101,4,38,-1,1105,1,9,99,7];
+        let expected = "    0: rb #3\n    2: add #4, #38, [rb-1]\n    6: jt #1, #9\n    9: hlt\n   10: data 7";
+        assert_eq!(listing(&program), expected);
+    }
+
+    #[test]
+    fn test_unwritable_modes_are_data() {
+        assert_eq!(listing(&[10104, 7, 104, 7]), "    0: data 10104\n    1: data 7\n    2: out #7");
+    }
+
+    #[test]
+    fn test_disassemble_program_file() {
+        let program = read_program_file(PathBuf::from("./assets/dev_program.txt")).unwrap();
+        assert_eq!(listing(&program), "    0: add [0], [0], [-42]\n    4: hlt");
+    }
+}
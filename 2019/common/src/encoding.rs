@@ -0,0 +1,96 @@
+//! Compact binary encoding shared by the on-disk formats: every integer is written as a
+//! zigzag LEB128 varint, so small values, positive or negative, take a single byte.
+
+use std::io::{self, Read, Write, ErrorKind};
+
+pub(crate) fn write_i64<W: Write>(writer: &mut W, value: i64) -> io::Result<()> {
+    let mut zigzag = ((value << 1) ^ (value >> 63)) as u64;
+    loop {
+        let byte = (zigzag & 0x7f) as u8;
+        zigzag >>= 7;
+        if zigzag == 0 {
+            return writer.write_all(&[byte])
+        }
+        writer.write_all(&[byte | 0x80])?;
+    }
+}
+
+pub(crate) fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    let mut zigzag: u64 = 0;
+    let mut shift = 0;
+    loop {
+        let mut byte = [0u8];
+        reader.read_exact(&mut byte)?;
+        if shift > 63 {
+            return Err(io::Error::new(ErrorKind::InvalidData, "varint is too long"))
+        }
+        zigzag |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok((zigzag >> 1) as i64 ^ -((zigzag & 1) as i64))
+        }
+        shift += 7;
+    }
+}
+
+pub(crate) fn write_len<W: Write>(writer: &mut W, len: usize) -> io::Result<()> {
+    write_i64(writer, len as i64)
+}
+
+pub(crate) fn read_len<R: Read>(reader: &mut R) -> io::Result<usize> {
+    match read_i64(reader)? {
+        len if len >= 0 => Ok(len as usize),
+        _ => Err(io::Error::new(ErrorKind::InvalidData, "negative length"))
+    }
+}
+
+pub(crate) fn write_string<W: Write>(writer: &mut W, string: &str) -> io::Result<()> {
+    write_len(writer, string.len())?;
+    writer.write_all(string.as_bytes())
+}
+
+pub(crate) fn read_string<R: Read>(reader: &mut R) -> io::Result<String> {
+    let len = read_len(reader)?;
+    // a corrupted length fails on the missing bytes instead of allocating them upfront
+    let mut bytes = vec![];
+    reader.take(len as u64).read_to_end(&mut bytes)?;
+    if bytes.len() != len {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "truncated string"))
+    }
+    String::from_utf8(bytes).map_err(|_| io::Error::new(ErrorKind::InvalidData, "invalid UTF-8 string"))
+}
+
+/// Checks the magic bytes and returns the format version
+pub(crate) fn read_header<R: Read>(reader: &mut R, magic: &[u8; 4]) -> io::Result<u8> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != magic {
+        return Err(io::Error::new(ErrorKind::InvalidData, "unknown file format"))
+    }
+    Ok(header[4])
+}
+
+pub(crate) fn write_header<W: Write>(writer: &mut W, magic: &[u8; 4], version: u8) -> io::Result<()> {
+    writer.write_all(magic)?;
+    writer.write_all(&[version])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_i64, read_i64};
+
+    #[test]
+    fn test_varint_round_trip() {
+        let values = vec![0, 1, -1, 63, -64, 64, 1000, -42, 100000000000000, i64::MAX, i64::MIN];
+        let mut buffer = vec![];
+        for value in values.iter() {
+            write_i64(&mut buffer, *value).unwrap();
+        }
+        assert_eq!(buffer[..3], [0, 2, 1]);
+
+        let mut reader = &buffer[..];
+        for value in values.iter() {
+            assert_eq!(read_i64(&mut reader).unwrap(), *value);
+        }
+        assert!(read_i64(&mut reader).is_err());
+    }
+}
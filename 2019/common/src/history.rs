@@ -0,0 +1,153 @@
+//!
+//! Undo log of a computer, to run it backwards, see `Computer::enable_history`
+//!
+use std::collections::VecDeque;
+use crate::computer::State;
+use crate::trace::TraceEntry;
+
+/// What is needed to undo one instruction
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct UndoEntry {
+    /// The instruction, its writes, input and output
+    pub instruction: TraceEntry,
+    /// Relative base before the instruction
+    pub base: i64,
+    /// State of the computer before the instruction
+    pub state: State,
+    /// Cells the instruction created, by writing or reading them, removed again when it's undone
+    pub created: Vec<i64>
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct History {
+    entries: VecDeque<UndoEntry>,
+    /// Instructions kept, the oldest ones are forgotten past it
+    limit: Option<usize>
+}
+
+impl History {
+    pub(crate) fn new(limit: Option<usize>) -> History {
+        History { entries: VecDeque::new(), limit }
+    }
+
+    pub(crate) fn push(&mut self, entry: UndoEntry) {
+        if self.limit.is_some_and(|limit| self.entries.len() >= limit) {
+            self.entries.pop_front();
+        }
+        if self.limit != Some(0) {
+            self.entries.push_back(entry);
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<UndoEntry> {
+        self.entries.pop_back()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Number of instructions which can be undone
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Undo entries from the oldest to the latest
+    pub fn entries(&self) -> impl Iterator<Item = &UndoEntry> {
+        self.entries.iter()
+    }
+
+    /// Latest instruction which wrote the cell, and how many instructions ran since
+    pub fn last_write(&self, address: i64) -> Option<(&UndoEntry, usize)> {
+        self.entries.iter().rev().enumerate()
+            .find(|(_, entry)| entry.instruction.writes.iter().any(|write| write.address == address))
+            .map(|(age, entry)| (entry, age))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::computer::{Computer, MemoryWrite, ResumeMode, State};
+    use crate::memory::MemoryKind;
+
+    /// Counts the input down to 0, outputting each value
+    const COUNTDOWN: [i64; 14] = [3, 13, 4, 13, 1001, 13, -1, 13, 1005, 13, 2, 99, 0, 0];
+
+    #[test]
+    fn test_step_back_restores_the_machine() {
+        let mut computer = Computer::new(COUNTDOWN.to_vec());
+        computer.enable_history(None);
+        computer.add_input(2);
+        let before = computer.snapshot();
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[2, 1]);
+        assert_eq!(computer.history().unwrap().len(), 8);
+
+        let entry = computer.step_back().unwrap();
+        assert_eq!(entry.instruction.instruction_pointer, 11);
+        assert_eq!(computer.instruction_pointer(), 11);
+        assert_eq!(computer.state, State::Started);
+
+        while computer.step_back().is_some() {}
+        assert_eq!(computer.snapshot(), before);
+
+        // runs the same way again
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[2, 1]);
+    }
+
+    #[test]
+    fn test_step_back_removes_the_created_cells() {
+        // reads a cell never written and writes another one past the program
+        let program = vec![1001, 20, 1, 21, 99];
+        for kind in [MemoryKind::Dense, MemoryKind::Sparse, MemoryKind::CopyOnWrite] {
+            let mut computer = Computer::with_memory(program.clone(), kind.create());
+            computer.enable_history(None);
+            let before = computer.memory().cells();
+            assert_eq!(computer.run().unwrap(), State::Stopped);
+            assert_eq!(computer.memory().get(21), Some(1));
+
+            while computer.step_back().is_some() {}
+            assert_eq!(computer.memory().cells(), before, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_rewind_to_write() {
+        let mut computer = Computer::new(COUNTDOWN.to_vec());
+        computer.enable_history(Some(5));
+        computer.set_resume_mode(ResumeMode::Enable);
+        computer.add_input(3);
+        while computer.run().unwrap() == State::Paused {}
+
+        let history = computer.history().unwrap();
+        assert_eq!(history.len(), 5);
+        let (entry, age) = history.last_write(13).unwrap();
+        assert_eq!(entry.instruction.writes, vec![MemoryWrite { address: 13, old: 1, new: 0 }]);
+        assert_eq!(age, 2);
+
+        let entry = computer.rewind_to_write(13).unwrap();
+        assert_eq!(entry.instruction.instruction_pointer, 4);
+        assert_eq!(computer.instruction_pointer(), 4);
+        assert_eq!(computer.peek(13), 1);
+        assert_eq!(computer.outputs(), &[3, 2, 1]);
+        // the output is taken back with its instruction
+        assert_eq!(computer.step_back().unwrap().instruction.output, Some(1));
+        assert_eq!(computer.outputs(), &[3, 2]);
+
+        // older writes were forgotten
+        assert_eq!(computer.rewind_to_write(1), None);
+        assert_eq!(computer.instruction_pointer(), 2);
+        assert_eq!(computer.history().unwrap().len(), 1);
+
+        // patching the memory by hand can't be undone
+        computer.poke(13, 7);
+        assert!(computer.history().unwrap().is_empty());
+        assert_eq!(computer.step_back(), None);
+        assert_eq!(computer.peek(13), 7);
+    }
+}
@@ -0,0 +1,265 @@
+//!
+//! Where a computer takes its inputs from and sends its outputs to, see
+//! `Computer::set_input_source` and `Computer::set_output_sink`
+//!
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::num::ParseIntError;
+use std::sync::mpsc::{Receiver, Sender};
+
+///
+/// Provides the values read by the store instruction
+///
+pub trait InputSource: Send {
+    /// Next value, `None` when nothing is available: the computer then awaits input
+    fn next_input(&mut self) -> Option<i64>;
+
+    /// Why the last `next_input` gave nothing if it is an error, the computer then fails with it
+    fn take_error(&mut self) -> Option<InputError> {
+        None
+    }
+}
+
+/// Token read by a source which isn't an integer
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct InputError {
+    pub token: String,
+    pub error: ParseIntError
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid input {:?}: {}", self.token, self.error)
+    }
+}
+
+///
+/// Receives the values written by the output instruction
+///
+pub trait OutputSink: Send {
+    fn send_output(&mut self, value: i64);
+}
+
+impl fmt::Debug for dyn InputSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "InputSource")
+    }
+}
+
+impl fmt::Debug for dyn OutputSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OutputSink")
+    }
+}
+
+/// Input computed by a closure
+pub struct InputFn<F>(pub F);
+
+impl<F: FnMut() -> Option<i64> + Send> InputSource for InputFn<F> {
+    fn next_input(&mut self) -> Option<i64> {
+        (self.0)()
+    }
+}
+
+/// Output handed to a closure
+pub struct OutputFn<F>(pub F);
+
+impl<F: FnMut(i64) + Send> OutputSink for OutputFn<F> {
+    fn send_output(&mut self, value: i64) {
+        (self.0)(value)
+    }
+}
+
+/// Input taken from an iterator, exhausted with it
+pub struct IterInput<I>(pub I);
+
+impl<I: Iterator<Item = i64> + Send> InputSource for IterInput<I> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.0.next()
+    }
+}
+
+/// Blocks until a value is received, nothing more comes once every sender is dropped
+impl InputSource for Receiver<i64> {
+    fn next_input(&mut self) -> Option<i64> {
+        self.recv().ok()
+    }
+}
+
+/// Outputs sent after the receiver is dropped are lost
+impl OutputSink for Sender<i64> {
+    fn send_output(&mut self, value: i64) {
+        let _ = self.send(value);
+    }
+}
+
+///
+/// Integers separated by commas or whitespace, read from a file, stdin...
+/// Input ends with the reader or at the first token which isn't an integer, which is reported
+/// by `take_error`. Nothing after it is read.
+///
+pub struct ReaderInput<R> {
+    reader: R,
+    tokens: Vec<String>,
+    ended: bool,
+    error: Option<InputError>
+}
+
+impl<R: BufRead + Send> ReaderInput<R> {
+    pub fn new(reader: R) -> ReaderInput<R> {
+        ReaderInput {
+            reader,
+            tokens: vec![],
+            ended: false,
+            error: None
+        }
+    }
+}
+
+impl<R: BufRead + Send> InputSource for ReaderInput<R> {
+    fn next_input(&mut self) -> Option<i64> {
+        if self.ended {
+            return None
+        }
+        while self.tokens.is_empty() {
+            let mut line = String::new();
+            match self.reader.read_line(&mut line) {
+                Ok(0) | Err(_) => {
+                    self.ended = true;
+                    return None
+                },
+                Ok(_) => {
+                    self.tokens = line.split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|token| !token.is_empty())
+                        .rev()
+                        .map(String::from)
+                        .collect();
+                }
+            }
+        }
+        let token = self.tokens.pop().unwrap();
+        match token.parse() {
+            Ok(value) => Some(value),
+            Err(error) => {
+                self.ended = true;
+                self.error = Some(InputError { token, error });
+                None
+            }
+        }
+    }
+
+    fn take_error(&mut self) -> Option<InputError> {
+        self.error.take()
+    }
+}
+
+/// One value per line, flushed at once so an interactive reader sees it
+pub struct WriterOutput<W>(pub W);
+
+impl<W: Write + Send> OutputSink for WriterOutput<W> {
+    fn send_output(&mut self, value: i64) {
+        let _ = writeln!(self.0, "{}", value).and_then(|_| self.0.flush());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::io::{InputFn, OutputFn, IterInput, ReaderInput, WriterOutput, InputSource, OutputSink};
+    use crate::computer::{Computer, IntcodeError, State};
+    use std::sync::mpsc::channel;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// Adds every input to the previous sum and outputs it, until an input is 0
+    const RUNNING_SUM: [i64; 14] = [3, 13, 1, 12, 13, 12, 4, 12, 1005, 13, 0, 99, 0, 0];
+
+    #[test]
+    fn test_closure_source_and_sink() {
+        let mut inputs = vec![0, 3, 2, 1];
+        let outputs = Arc::new(Mutex::new(vec![]));
+        let collected = Arc::clone(&outputs);
+
+        let mut computer = Computer::new(RUNNING_SUM.to_vec());
+        computer.set_input_source(InputFn(move || inputs.pop()));
+        computer.set_output_sink(OutputFn(move |value| collected.lock().unwrap().push(value)));
+
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(*outputs.lock().unwrap(), vec![1, 3, 6, 6]);
+        assert!(computer.outputs().is_empty());
+    }
+
+    #[test]
+    fn test_queued_inputs_come_before_the_source() {
+        let mut computer = Computer::new(RUNNING_SUM.to_vec());
+        computer.add_input(5);
+        computer.set_input_source(IterInput(vec![1, 0].into_iter()));
+
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[5, 6, 6]);
+    }
+
+    #[test]
+    fn test_exhausted_source_awaits_input() {
+        let mut computer = Computer::new(RUNNING_SUM.to_vec());
+        computer.set_input_source(IterInput(vec![4].into_iter()));
+
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+        computer.add_input(0);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[4, 4]);
+    }
+
+    #[test]
+    fn test_channels() {
+        let (input_sender, input_receiver) = channel();
+        let (output_sender, output_receiver) = channel();
+
+        let mut computer = Computer::new(RUNNING_SUM.to_vec());
+        computer.set_input_source(input_receiver);
+        computer.set_output_sink(output_sender);
+        let handle = thread::spawn(move || computer.run().unwrap());
+
+        input_sender.send(10).unwrap();
+        assert_eq!(output_receiver.recv().unwrap(), 10);
+        input_sender.send(-3).unwrap();
+        assert_eq!(output_receiver.recv().unwrap(), 7);
+        input_sender.send(0).unwrap();
+        assert_eq!(output_receiver.recv().unwrap(), 7);
+        assert_eq!(handle.join().unwrap(), State::Stopped);
+    }
+
+    #[test]
+    fn test_reader_and_writer() {
+        let mut source = ReaderInput::new("1, 2\n\n  -3,x\n4".as_bytes());
+        assert_eq!(source.next_input(), Some(1));
+        assert_eq!(source.next_input(), Some(2));
+        assert_eq!(source.next_input(), Some(-3));
+        assert_eq!(source.take_error(), None);
+        assert_eq!(source.next_input(), None);
+        let error = source.take_error().unwrap();
+        assert_eq!(error.token, "x");
+        assert_eq!(error.to_string(), "invalid input \"x\": invalid digit found in string");
+        // the values following the malformed token are never read
+        assert_eq!(source.next_input(), None);
+        assert_eq!(source.take_error(), None);
+
+        let mut computer = Computer::new(RUNNING_SUM.to_vec());
+        computer.set_input_source(ReaderInput::new("2 3".as_bytes()));
+        assert_eq!(computer.run().unwrap(), State::AwaitingInput);
+        assert_eq!(computer.outputs(), &[2, 5]);
+
+        let mut computer = Computer::new(RUNNING_SUM.to_vec());
+        computer.set_input_source(ReaderInput::new("2 3 0x10 0".as_bytes()));
+        match computer.run() {
+            Err(IntcodeError::InvalidInput { instruction_pointer: 0, opcode: 3, error }) => assert_eq!(error.token, "0x10"),
+            result => panic!("{:?}", result)
+        }
+        assert_eq!(computer.outputs(), &[2, 5]);
+        assert_eq!(computer.instruction_pointer(), 0);
+
+        let mut sink = WriterOutput(vec![]);
+        sink.send_output(12);
+        sink.send_output(-1);
+        assert_eq!(String::from_utf8(sink.0).unwrap(), "12\n-1\n");
+    }
+}
@@ -1,2 +1,21 @@
 pub mod computer;
+pub mod disasm;
+pub mod asm;
+pub mod debugger;
+pub mod trace;
+pub mod snapshot;
+pub mod memory;
+pub mod io;
+pub mod pipeline;
+pub mod network;
+pub mod profile;
+pub mod coverage;
+pub mod ascii;
+pub mod search;
+pub mod loader;
+pub mod bignum;
+pub mod registry;
+pub mod device;
+pub mod history;
+mod encoding;
 mod macros;
\ No newline at end of file
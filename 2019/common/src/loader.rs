@@ -0,0 +1,165 @@
+//!
+//! Program loader. Values are separated by commas, spaces or line breaks, and `#` starts a
+//! comment running to the end of the line:
+//!
+//! ```text
+//! # adds 4 and 5
+//! 1101, 4, 5, 7,
+//! 99, 0
+//! ```
+//!
+//! Anything which isn't an integer is reported with its position instead of being dropped.
+//!
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, BufReader};
+use std::path::PathBuf;
+use std::process;
+use flate2::read::GzDecoder;
+
+/// First bytes of a gzip file
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum LoadErrorKind {
+    /// The token isn't an integer
+    InvalidValue(String),
+    /// Nothing between two commas, or before the first one
+    MissingValue
+}
+
+/// A malformed program, `line` and `column` being 1-based and counted in characters
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct LoadError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: LoadErrorKind
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}: ", self.line, self.column)?;
+        match &self.kind {
+            LoadErrorKind::InvalidValue(token) => write!(f, "invalid value {}", token),
+            LoadErrorKind::MissingValue => write!(f, "missing value")
+        }
+    }
+}
+
+impl Error for LoadError {}
+
+/// Parses the text of a program, a comma at the very end being allowed
+pub fn parse_program(source: &str) -> Result<Vec<i64>, LoadError> {
+    let mut program = vec![];
+    // a comma was read and no value after it yet, true at first to reject a leading comma
+    let mut after_comma = true;
+
+    for (index, line) in source.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("");
+        let mut token = String::new();
+        let mut token_column = 0;
+
+        let mut push_token = |token: &mut String, column: usize, after_comma: &mut bool| -> Result<(), LoadError> {
+            if token.is_empty() {
+                return Ok(())
+            }
+            match token.parse::<i64>() {
+                Ok(value) => program.push(value),
+                Err(_) => return Err(LoadError { line: index + 1, column, kind: LoadErrorKind::InvalidValue(token.clone()) })
+            }
+            token.clear();
+            *after_comma = false;
+            Ok(())
+        };
+
+        for (offset, character) in line.chars().enumerate() {
+            let column = offset + 1;
+            if character == ',' {
+                push_token(&mut token, token_column, &mut after_comma)?;
+                if after_comma {
+                    return Err(LoadError { line: index + 1, column, kind: LoadErrorKind::MissingValue });
+                }
+                after_comma = true;
+            } else if character.is_whitespace() {
+                push_token(&mut token, token_column, &mut after_comma)?;
+            } else {
+                if token.is_empty() {
+                    token_column = column;
+                }
+                token.push(character);
+            }
+        }
+        push_token(&mut token, token_column, &mut after_comma)?;
+    }
+    Ok(program)
+}
+
+/// Reads a program, gzip-compressed or not
+pub fn load_program<R: Read>(reader: R) -> Result<Vec<i64>, Box<dyn Error>> {
+    let mut bytes = vec![];
+    BufReader::new(reader).read_to_end(&mut bytes)?;
+
+    let mut source = String::new();
+    if bytes.starts_with(&GZIP_MAGIC) {
+        GzDecoder::new(&bytes[..]).read_to_string(&mut source)?;
+    } else {
+        source = String::from_utf8(bytes)?;
+    }
+    Ok(parse_program(&source)?)
+}
+
+pub fn load_program_file(path: PathBuf) -> Result<Vec<i64>, Box<dyn Error>> {
+    load_program(File::open(&path)?)
+}
+
+pub fn load_program_stdin() -> Result<Vec<i64>, Box<dyn Error>> {
+    load_program(io::stdin())
+}
+
+/// Prints the message on stderr and ends the process with a failure status, for the binaries
+pub fn exit_with(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_program, load_program, LoadError, LoadErrorKind};
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    #[test]
+    fn test_parse_program() {
+        assert_eq!(parse_program("1,0,0,3,99\n").unwrap(), vec![1, 0, 0, 3, 99]);
+        let source = "
+            # adds 4 and 5
+            1101, 4, 5, 7,   # into the last cell
+
+            99, 0,
+        ";
+        assert_eq!(parse_program(source).unwrap(), vec![1101, 4, 5, 7, 99, 0]);
+        assert_eq!(parse_program("1\n2 3,-4").unwrap(), vec![1, 2, 3, -4]);
+        assert!(parse_program("").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_malformed_tokens_are_reported() {
+        assert_eq!(parse_program("1,2,\n3, 1O2,99"), Err(LoadError { line: 2, column: 4, kind: LoadErrorKind::InvalidValue("1O2".to_string()) }));
+        assert_eq!(parse_program("1,,2"), Err(LoadError { line: 1, column: 3, kind: LoadErrorKind::MissingValue }));
+        assert_eq!(parse_program(",1"), Err(LoadError { line: 1, column: 1, kind: LoadErrorKind::MissingValue }));
+        assert_eq!(parse_program("1, 99999999999999999999").unwrap_err().to_string(), "line 1, column 4: invalid value 99999999999999999999");
+    }
+
+    #[test]
+    fn test_load_gzip() {
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(b"109,-1,4,1,99\n").unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        assert_eq!(load_program(&compressed[..]).unwrap(), vec![109, -1, 4, 1, 99]);
+        assert_eq!(load_program("109,-1\n".as_bytes()).unwrap(), vec![109, -1]);
+        assert!(load_program(&b"1,\xff"[..]).is_err());
+    }
+}
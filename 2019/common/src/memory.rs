@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Addresses below this limit live in the vector of the dense memory
+const DENSE_LIMIT: i64 = 1 << 22;
+
+/// Cells per page of the copy-on-write memory
+const PAGE_SIZE: i64 = 1024;
+
+///
+/// Storage of the computer cells, a cell exists once it has been written
+///
+pub trait Memory: fmt::Debug + Send {
+    /// Value of the cell, `None` if it has never been written
+    fn get(&self, address: i64) -> Option<i64>;
+
+    /// Writes the cell and returns its previous value
+    fn set(&mut self, address: i64, value: i64) -> Option<i64>;
+
+    /// Forgets the cell as if it had never been written and returns its value
+    fn remove(&mut self, address: i64) -> Option<i64>;
+
+    /// Every existing cell, sorted by address
+    fn cells(&self) -> Vec<(i64, i64)>;
+
+    /// Independent copy of the memory
+    fn fork(&self) -> Box<dyn Memory>;
+
+    /// Backend of the memory
+    fn kind(&self) -> MemoryKind;
+
+    /// Reads the cell, creating it with `default` if it doesn't exist yet
+    fn get_or_insert(&mut self, address: i64, default: i64) -> i64 {
+        match self.get(address) {
+            Some(value) => value,
+            None => {
+                self.set(address, default);
+                default
+            }
+        }
+    }
+}
+
+/// Which memory backend a computer should use
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum MemoryKind {
+    /// The default backend, suiting ordinary programs as its far-away cells spill in a map
+    Dense,
+    Sparse,
+    /// Pages shared between forks until one of them writes
+    CopyOnWrite
+}
+
+impl MemoryKind {
+    pub fn create(self) -> Box<dyn Memory> {
+        match self {
+            MemoryKind::Dense => Box::new(DenseMemory::default()),
+            MemoryKind::Sparse => Box::new(SparseMemory::default()),
+            MemoryKind::CopyOnWrite => Box::new(CowMemory::default())
+        }
+    }
+}
+
+///
+/// Growable vector indexed by address, for ordinary programs whose cells are packed from 0.
+/// Negative addresses and addresses past `DENSE_LIMIT` are kept in a map instead, so a single
+/// far-away cell doesn't allocate gigabytes.
+///
+#[derive(Debug, Clone, Default)]
+pub struct DenseMemory {
+    cells: Vec<Option<i64>>,
+    spill: HashMap<i64, i64>
+}
+
+impl Memory for DenseMemory {
+    fn get(&self, address: i64) -> Option<i64> {
+        if (0..DENSE_LIMIT).contains(&address) {
+            self.cells.get(address as usize).copied().flatten()
+        } else {
+            self.spill.get(&address).copied()
+        }
+    }
+
+    fn set(&mut self, address: i64, value: i64) -> Option<i64> {
+        if (0..DENSE_LIMIT).contains(&address) {
+            let index = address as usize;
+            if index >= self.cells.len() {
+                self.cells.resize(index + 1, None);
+            }
+            self.cells[index].replace(value)
+        } else {
+            self.spill.insert(address, value)
+        }
+    }
+
+    fn remove(&mut self, address: i64) -> Option<i64> {
+        if (0..DENSE_LIMIT).contains(&address) {
+            self.cells.get_mut(address as usize).and_then(|cell| cell.take())
+        } else {
+            self.spill.remove(&address)
+        }
+    }
+
+    fn cells(&self) -> Vec<(i64, i64)> {
+        let mut cells = self.cells
+            .iter()
+            .enumerate()
+            .filter_map(|(address, value)| value.map(|value| (address as i64, value)))
+            .chain(self.spill.iter().map(|(&address, &value)| (address, value)))
+            .collect::<Vec<(i64, i64)>>();
+        cells.sort();
+        cells
+    }
+
+    fn fork(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+
+    fn kind(&self) -> MemoryKind {
+        MemoryKind::Dense
+    }
+}
+
+/// Map from address to value, for programs spreading their data over huge addresses
+#[derive(Debug, Clone, Default)]
+pub struct SparseMemory {
+    cells: HashMap<i64, i64>
+}
+
+impl Memory for SparseMemory {
+    fn get(&self, address: i64) -> Option<i64> {
+        self.cells.get(&address).copied()
+    }
+
+    fn set(&mut self, address: i64, value: i64) -> Option<i64> {
+        self.cells.insert(address, value)
+    }
+
+    fn remove(&mut self, address: i64) -> Option<i64> {
+        self.cells.remove(&address)
+    }
+
+    fn cells(&self) -> Vec<(i64, i64)> {
+        let mut cells = self.cells.iter().map(|(&address, &value)| (address, value)).collect::<Vec<(i64, i64)>>();
+        cells.sort();
+        cells
+    }
+
+    fn fork(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+
+    fn kind(&self) -> MemoryKind {
+        MemoryKind::Sparse
+    }
+}
+
+///
+/// Memory split in pages shared by the forks of a computer, a page being copied by the first
+/// fork writing to it. Forking only copies the page table, so searches can keep thousands of
+/// machines alive.
+///
+#[derive(Debug, Clone, Default)]
+pub struct CowMemory {
+    pages: HashMap<i64, Arc<Vec<Option<i64>>>>
+}
+
+fn page_of(address: i64) -> (i64, usize) {
+    (address.div_euclid(PAGE_SIZE), address.rem_euclid(PAGE_SIZE) as usize)
+}
+
+impl Memory for CowMemory {
+    fn get(&self, address: i64) -> Option<i64> {
+        let (page, offset) = page_of(address);
+        self.pages.get(&page).and_then(|page| page[offset])
+    }
+
+    fn set(&mut self, address: i64, value: i64) -> Option<i64> {
+        let (page, offset) = page_of(address);
+        let page = self.pages.entry(page).or_insert_with(|| Arc::new(vec![None; PAGE_SIZE as usize]));
+        Arc::make_mut(page)[offset].replace(value)
+    }
+
+    fn remove(&mut self, address: i64) -> Option<i64> {
+        let (page, offset) = page_of(address);
+        match self.pages.get_mut(&page) {
+            // no copy of a shared page just to find the cell empty
+            Some(page) if page[offset].is_some() => Arc::make_mut(page)[offset].take(),
+            _ => None
+        }
+    }
+
+    fn cells(&self) -> Vec<(i64, i64)> {
+        let mut cells = self.pages
+            .iter()
+            .flat_map(|(page, cells)| {
+                cells.iter().enumerate().filter_map(move |(offset, value)| {
+                    value.map(|value| (page * PAGE_SIZE + offset as i64, value))
+                })
+            })
+            .collect::<Vec<(i64, i64)>>();
+        cells.sort();
+        cells
+    }
+
+    fn fork(&self) -> Box<dyn Memory> {
+        Box::new(self.clone())
+    }
+
+    fn kind(&self) -> MemoryKind {
+        MemoryKind::CopyOnWrite
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Memory, MemoryKind, DenseMemory, CowMemory, DENSE_LIMIT};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_backends_behave_the_same() {
+        for kind in [MemoryKind::Dense, MemoryKind::Sparse, MemoryKind::CopyOnWrite] {
+            let mut memory = kind.create();
+            assert_eq!(memory.get(3), None);
+            assert_eq!(memory.set(3, 42), None);
+            assert_eq!(memory.set(3, 43), Some(42));
+            assert_eq!(memory.get(3), Some(43));
+            assert_eq!(memory.get(2), None);
+            assert_eq!(memory.get_or_insert(2, -1), -1);
+            assert_eq!(memory.get_or_insert(2, 5), -1);
+            memory.set(100000000000000, 7);
+            memory.set(-5, 8);
+            assert_eq!(memory.cells(), vec![(-5, 8), (2, -1), (3, 43), (100000000000000, 7)]);
+            assert_eq!(memory.remove(-5), Some(8));
+            assert_eq!(memory.remove(2), Some(-1));
+            assert_eq!(memory.remove(2), None);
+            assert_eq!(memory.remove(100000000000000), Some(7));
+            assert_eq!(memory.cells(), vec![(3, 43)]);
+
+            let mut fork = memory.fork();
+            assert_eq!(fork.kind(), kind);
+            fork.set(3, 0);
+            assert_eq!(memory.get(3), Some(43));
+            assert_eq!(fork.get(3), Some(0));
+        }
+    }
+
+    #[test]
+    fn test_cow_memory_shares_pages_until_written() {
+        let mut memory = CowMemory::default();
+        memory.set(5, 1);
+        memory.set(5000, 2);
+        let mut fork = memory.clone();
+        fork.set(5001, 3);
+        assert!(Arc::ptr_eq(&memory.pages[&0], &fork.pages[&0]));
+        assert!(!Arc::ptr_eq(&memory.pages[&4], &fork.pages[&4]));
+        assert_eq!(memory.get(5001), None);
+        assert_eq!(fork.cells(), vec![(5, 1), (5000, 2), (5001, 3)]);
+    }
+
+    #[test]
+    fn test_dense_memory_spills_far_addresses() {
+        let mut memory = DenseMemory::default();
+        memory.set(DENSE_LIMIT, 1);
+        memory.set(10, 2);
+        assert_eq!(memory.cells.len(), 11);
+        assert_eq!(memory.get(DENSE_LIMIT), Some(1));
+    }
+}
@@ -0,0 +1,353 @@
+//!
+//! Single-threaded scheduler of computers exchanging packets.
+//!
+//! A machine sends a packet by outputting its destination address then `x` and `y`. Packets
+//! to an address outside the network go to the monitor. A machine reading while nothing was
+//! sent to it gets -1.
+//!
+//! The network is idle once every machine keeps getting -1 and no packet moves, the monitor
+//! then decides whether to wake it up. A machine that neither reads nor halts gives its turn up
+//! after `TURN_BUDGET` instructions, so it can't stall the others.
+//!
+use crate::computer::{Computer, IntcodeError, ResumeMode, State};
+
+/// Address of the NAT in the puzzle networks
+pub const NAT_ADDRESS: i64 = 255;
+
+/// Reads of -1 in a row after which a machine is considered idle
+const IDLE_POLLS: usize = 2;
+
+/// Instructions a machine executes at most in one turn
+const TURN_BUDGET: u64 = 10_000;
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub struct Packet {
+    pub destination: i64,
+    pub x: i64,
+    pub y: i64
+}
+
+///
+/// Watches the traffic leaving the network and wakes the network up when it is idle
+///
+pub trait NetworkMonitor {
+    /// Packet sent outside the network, returns false to stop the network
+    fn receive(&mut self, packet: Packet) -> bool;
+
+    /// Packet sent to wake the idle network up, `None` stops it. A packet to an address outside
+    /// the network stops it too, after being handed to `receive`
+    fn on_idle(&mut self) -> Option<Packet>;
+}
+
+///
+/// Keeps the last packet sent to `NAT_ADDRESS` and sends it to the machine 0 when the network
+/// is idle. Stops the network when it would send the same `y` twice in a row.
+///
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Nat {
+    first: Option<Packet>,
+    last: Option<Packet>,
+    delivered: Vec<i64>,
+    repeated: Option<i64>
+}
+
+impl Nat {
+    pub fn new() -> Nat {
+        Nat::default()
+    }
+
+    /// First packet received by the NAT
+    pub fn first_packet(&self) -> Option<Packet> {
+        self.first
+    }
+
+    /// `y` values sent to the machine 0, in order
+    pub fn delivered(&self) -> &[i64] {
+        &self.delivered
+    }
+
+    /// The `y` which was about to be sent twice in a row when the NAT stopped the network
+    pub fn repeated_y(&self) -> Option<i64> {
+        self.repeated
+    }
+}
+
+impl NetworkMonitor for Nat {
+    fn receive(&mut self, packet: Packet) -> bool {
+        if packet.destination == NAT_ADDRESS {
+            self.first.get_or_insert(packet);
+            self.last = Some(packet);
+        }
+        true
+    }
+
+    fn on_idle(&mut self) -> Option<Packet> {
+        let packet = self.last?;
+        if self.delivered.last() == Some(&packet.y) {
+            self.repeated = Some(packet.y);
+            return None
+        }
+        self.delivered.push(packet.y);
+        Some(Packet { destination: 0, ..packet })
+    }
+}
+
+#[derive(Debug)]
+struct Machine {
+    computer: Computer,
+    /// Outputs of a packet not fully sent yet
+    partial: Vec<i64>,
+    /// A packet was queued since the last time the machine ran
+    has_input: bool,
+    idle_polls: usize
+}
+
+#[derive(Debug)]
+pub struct Network {
+    machines: Vec<Machine>
+}
+
+impl Network {
+    /// `size` machines running `program`, each one given its address as first input
+    pub fn new(program: &[i64], size: usize) -> Network {
+        let machines = (0..size).map(|address| {
+            let mut computer = Computer::new(program.to_vec());
+            computer.set_resume_mode(ResumeMode::Enable);
+            computer.set_instruction_budget(Some(TURN_BUDGET));
+            computer.add_input(address as i64);
+            Machine {
+                computer,
+                partial: vec![],
+                has_input: true,
+                idle_polls: 0
+            }
+        }).collect();
+
+        Network { machines }
+    }
+
+    pub fn machine(&self, address: i64) -> Option<&Computer> {
+        if address < 0 {
+            return None
+        }
+        self.machines.get(address as usize).map(|machine| &machine.computer)
+    }
+
+    /// Queues the packet to its destination, returns false if no machine has this address
+    pub fn send(&mut self, packet: Packet) -> bool {
+        if packet.destination < 0 {
+            return false
+        }
+        match self.machines.get_mut(packet.destination as usize) {
+            Some(machine) => {
+                machine.computer.add_input(packet.x);
+                machine.computer.add_input(packet.y);
+                machine.has_input = true;
+                machine.idle_polls = 0;
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Gives each machine its turn until the monitor stops the network or every machine halts
+    pub fn run<M: NetworkMonitor>(&mut self, monitor: &mut M) -> Result<(), IntcodeError> {
+        loop {
+            let mut moved = false;
+            for address in 0..self.machines.len() {
+                for packet in self.run_machine(address)? {
+                    moved = true;
+                    if !self.send(packet) && !monitor.receive(packet) {
+                        return Ok(())
+                    }
+                }
+            }
+
+            let running: Vec<&Machine> = self.machines.iter()
+                .filter(|machine| machine.computer.state != State::Stopped)
+                .collect();
+            if running.is_empty() {
+                return Ok(())
+            }
+
+            if !moved && running.iter().all(|machine| machine.idle_polls >= IDLE_POLLS) {
+                match monitor.on_idle() {
+                    Some(packet) if self.send(packet) => {},
+                    Some(packet) => {
+                        // Nothing in the network can be woken up by this packet
+                        monitor.receive(packet);
+                        return Ok(())
+                    },
+                    None => return Ok(())
+                }
+            }
+        }
+    }
+
+    /// Runs the machine until it reads again, halts or exhausts its turn, and returns the packets it sent
+    fn run_machine(&mut self, address: usize) -> Result<Vec<Packet>, IntcodeError> {
+        let machine = &mut self.machines[address];
+        match machine.computer.state {
+            State::Stopped => return Ok(vec![]),
+            State::AwaitingInput if !machine.has_input => {
+                machine.computer.add_input(-1);
+                machine.idle_polls += 1;
+            },
+            _ => ()
+        }
+        machine.has_input = false;
+
+        let mut packets = vec![];
+        loop {
+            let state = machine.computer.run()?;
+            for value in machine.computer.drain_outputs() {
+                machine.idle_polls = 0;
+                machine.partial.push(value);
+                if let [destination, x, y] = machine.partial[..] {
+                    packets.push(Packet { destination, x, y });
+                    machine.partial.clear();
+                }
+            }
+            // Interrupted when the turn is over, the machine goes on from there at its next turn
+            if state != State::Paused {
+                return Ok(packets)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::network::{Network, NetworkMonitor, Nat, Packet, NAT_ADDRESS};
+    use crate::asm::assemble;
+    use crate::computer::{IntcodeError, State};
+
+    /// Machine 0 sends (2, 10) to 1, then each machine decrements `x`, increments `y` and passes
+    /// the packet to the next one, the last machine sending to the NAT. A packet with `x` at 0 is dropped.
+    const RELAY: &str = "
+                in [addr]
+                add [addr], #1, [next]
+                eq [next], #3, [tmp]
+                mul [tmp], #252, [tmp]
+                add [next], [tmp], [next]
+                jt [addr], #loop
+                add #3, #0, [x]
+                add #10, #0, [y]
+                jt #1, #send
+        loop:   in [x]
+                eq [x], #-1, [tmp]
+                jt [tmp], #loop
+                in [y]
+                jf [x], #loop
+                add [y], #1, [y]
+        send:   add [x], #-1, [x]
+                out [next]
+                out [x]
+                out [y]
+                jt #1, #loop
+        addr:   data 0
+        next:   data 0
+        x:      data 0
+        y:      data 0
+        tmp:    data 0";
+
+    /// Records every packet leaving the network and sends `wake_up` at the first idle,
+    /// stops the network once `stop_after` packets are recorded
+    #[derive(Default)]
+    struct Recorder {
+        packets: Vec<Packet>,
+        idle: usize,
+        wake_up: Option<Packet>,
+        stop_after: Option<usize>
+    }
+
+    impl NetworkMonitor for Recorder {
+        fn receive(&mut self, packet: Packet) -> bool {
+            self.packets.push(packet);
+            self.stop_after != Some(self.packets.len())
+        }
+
+        fn on_idle(&mut self) -> Option<Packet> {
+            self.idle += 1;
+            self.wake_up.take()
+        }
+    }
+
+    #[test]
+    fn test_packets_are_routed_until_idle() {
+        let mut network = Network::new(&assemble(RELAY).unwrap(), 3);
+        let mut recorder = Recorder::default();
+        network.run(&mut recorder).unwrap();
+
+        assert_eq!(recorder.packets, vec![Packet { destination: NAT_ADDRESS, x: 0, y: 12 }]);
+        assert_eq!(recorder.idle, 1);
+    }
+
+    #[test]
+    fn test_wake_up_outside_the_network() {
+        let mut network = Network::new(&assemble(RELAY).unwrap(), 3);
+        let stray = Packet { destination: 7, x: 1, y: 1 };
+        let mut recorder = Recorder { wake_up: Some(stray), ..Recorder::default() };
+        network.run(&mut recorder).unwrap();
+
+        assert_eq!(recorder.packets, vec![Packet { destination: NAT_ADDRESS, x: 0, y: 12 }, stray]);
+        assert_eq!(recorder.idle, 1);
+    }
+
+    #[test]
+    fn test_nat_wakes_the_network() {
+        let mut network = Network::new(&assemble(RELAY).unwrap(), 3);
+        let mut nat = Nat::new();
+        network.run(&mut nat).unwrap();
+
+        assert_eq!(nat.first_packet(), Some(Packet { destination: NAT_ADDRESS, x: 0, y: 12 }));
+        assert_eq!(nat.delivered(), &[12]);
+        assert_eq!(nat.repeated_y(), Some(12));
+    }
+
+    #[test]
+    fn test_network_stops_when_every_machine_halts() {
+        // Sends (address, 7) to the NAT and halts
+        let program = assemble("
+                in [addr]
+                out #255
+                out [addr]
+                out #7
+                hlt
+        addr:   data 0").unwrap();
+        let mut network = Network::new(&program, 4);
+        let mut recorder = Recorder::default();
+        network.run(&mut recorder).unwrap();
+
+        let senders: Vec<i64> = recorder.packets.iter().map(|packet| packet.x).collect();
+        assert_eq!(senders, vec![0, 1, 2, 3]);
+        assert_eq!(recorder.idle, 0);
+
+        let mut network = Network::new(&[3, 0, 42], 2);
+        assert_eq!(network.run(&mut recorder), Err(IntcodeError::UnknownOpCode {
+            instruction_pointer: 2,
+            opcode: 42
+        }));
+    }
+
+    #[test]
+    fn test_busy_machine_yields_its_turn() {
+        // The machine 0 spins forever, the others send (address, 7) to the monitor and halt
+        let program = assemble("
+                in [addr]
+                jf [addr], #spin
+                out #255
+                out [addr]
+                out #7
+                hlt
+        spin:   jt #1, #spin
+        addr:   data 0").unwrap();
+        let mut network = Network::new(&program, 3);
+        let mut recorder = Recorder { stop_after: Some(2), ..Recorder::default() };
+        network.run(&mut recorder).unwrap();
+
+        let senders: Vec<i64> = recorder.packets.iter().map(|packet| packet.x).collect();
+        assert_eq!(senders, vec![1, 2]);
+        assert_eq!(network.machine(0).unwrap().state, State::Interrupted);
+    }
+}
@@ -0,0 +1,399 @@
+//!
+//! Runs several computers at once, one thread each, the outputs of a node being sent to the
+//! nodes it is connected to through `mpsc` channels.
+//!
+//! A node whose upstream nodes have all halted runs out of input and stops too. When every node
+//! still running waits for input with no value on its way, the pipeline is deadlocked and those
+//! nodes are stopped.
+//!
+use std::sync::mpsc::{channel, Sender, Receiver, RecvTimeoutError};
+use std::any::Any;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use crate::computer::{Computer, IntcodeError, State};
+use crate::io::{InputSource, OutputSink};
+
+/// How long a waiting node sleeps before checking for a deadlock
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+enum NodeStatus {
+    Running,
+    Waiting,
+    Done
+}
+
+/// What every node is doing, shared by the threads to detect deadlocks
+#[derive(Debug)]
+struct Monitor {
+    status: Vec<NodeStatus>,
+    /// Values sent to each node and not received yet
+    pending: Vec<usize>,
+    deadlocked: bool
+}
+
+type SharedMonitor = Arc<Mutex<Monitor>>;
+
+impl Monitor {
+    fn set_status(&mut self, node: usize, status: NodeStatus) {
+        self.status[node] = status;
+    }
+
+    fn check_deadlock(&mut self) -> bool {
+        if !self.deadlocked {
+            self.deadlocked = self.status.iter().zip(self.pending.iter())
+                .all(|(status, pending)| match status {
+                    NodeStatus::Done => true,
+                    NodeStatus::Waiting => *pending == 0,
+                    NodeStatus::Running => false
+                });
+        }
+        self.deadlocked
+    }
+}
+
+/// Receiving end of a node
+struct Inbox {
+    node: usize,
+    receiver: Receiver<i64>,
+    monitor: SharedMonitor
+}
+
+impl Inbox {
+    fn received(&self, value: i64) -> Option<i64> {
+        let mut monitor = self.monitor.lock().unwrap();
+        monitor.pending[self.node] -= 1;
+        monitor.set_status(self.node, NodeStatus::Running);
+        Some(value)
+    }
+}
+
+impl InputSource for Inbox {
+    fn next_input(&mut self) -> Option<i64> {
+        if let Ok(value) = self.receiver.try_recv() {
+            return self.received(value);
+        }
+
+        self.monitor.lock().unwrap().set_status(self.node, NodeStatus::Waiting);
+        loop {
+            match self.receiver.recv_timeout(POLL_INTERVAL) {
+                Ok(value) => return self.received(value),
+                Err(RecvTimeoutError::Disconnected) => return None,
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.monitor.lock().unwrap().check_deadlock() {
+                        return None
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Sending end of a node, every output goes to all of its targets
+struct Fanout {
+    targets: Vec<(usize, Sender<i64>)>,
+    monitor: SharedMonitor
+}
+
+impl OutputSink for Fanout {
+    fn send_output(&mut self, value: i64) {
+        for (node, sender) in self.targets.iter() {
+            // Counted before sending, so the value is never unaccounted for while in the channel
+            self.monitor.lock().unwrap().pending[*node] += 1;
+            if sender.send(value).is_err() {
+                self.monitor.lock().unwrap().pending[*node] -= 1;
+            }
+        }
+    }
+}
+
+/// Marks the node as done even if its thread panics
+struct Departure {
+    node: usize,
+    monitor: SharedMonitor
+}
+
+impl Drop for Departure {
+    fn drop(&mut self) {
+        if let Ok(mut monitor) = self.monitor.lock() {
+            monitor.set_status(self.node, NodeStatus::Done);
+        }
+    }
+}
+
+/// Why a node stopped
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum NodeOutcome {
+    Halted,
+    /// Its input ran out: no input connected, or every upstream node stopped
+    Starved,
+    Deadlocked,
+    Failed(IntcodeError),
+    /// Its thread panicked, in a device or an opcode handler for instance, with this message
+    Panicked(String)
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct NodeReport {
+    pub name: String,
+    pub outcome: NodeOutcome,
+    /// Every value output by the node, forwarded or not
+    pub outputs: Vec<i64>,
+    pub inputs: usize,
+    pub instructions: usize,
+    pub elapsed: Duration
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct PipelineReport {
+    /// In the order the nodes were added
+    pub nodes: Vec<NodeReport>
+}
+
+impl PipelineReport {
+    pub fn node(&self, name: &str) -> Option<&NodeReport> {
+        self.nodes.iter().find(|node| node.name == name)
+    }
+
+    pub fn deadlocked(&self) -> bool {
+        self.nodes.iter().any(|node| node.outcome == NodeOutcome::Deadlocked)
+    }
+}
+
+///
+/// Computers wired together, see the module documentation.
+///
+/// The inputs added to a computer before it is added are consumed first, so they give each
+/// node its setting or the value starting the pipeline. A node without incoming link keeps its
+/// own input source, a node without outgoing link its own output sink.
+///
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    nodes: Vec<(String, Computer)>,
+    links: Vec<(usize, usize)>
+}
+
+impl Pipeline {
+    pub fn new() -> Pipeline {
+        Pipeline::default()
+    }
+
+    /// Returns the node id used by `connect`
+    pub fn add_node(&mut self, name: &str, computer: Computer) -> usize {
+        self.nodes.push((name.to_string(), computer));
+        self.nodes.len() - 1
+    }
+
+    /// Sends the outputs of `from` to `to`, a node may be connected to several ones and to itself
+    pub fn connect(&mut self, from: usize, to: usize) {
+        assert!(from < self.nodes.len() && to < self.nodes.len(), "Unknown node");
+        self.links.push((from, to));
+    }
+
+    /// Runs every node until it stops, then reports on each of them
+    pub fn run(self) -> PipelineReport {
+        let start = Instant::now();
+        let count = self.nodes.len();
+        let monitor = Arc::new(Mutex::new(Monitor {
+            status: vec![NodeStatus::Running; count],
+            pending: vec![0; count],
+            deadlocked: false
+        }));
+
+        let mut senders = vec![];
+        let mut receivers = vec![];
+        for _ in 0..count {
+            let (sender, receiver) = channel();
+            senders.push(sender);
+            receivers.push(Some(receiver));
+        }
+
+        let names: Vec<String> = self.nodes.iter().map(|(name, _)| name.clone()).collect();
+        let mut handles = vec![];
+        for (node, (name, mut computer)) in self.nodes.into_iter().enumerate() {
+            if self.links.iter().any(|(_, to)| *to == node) {
+                computer.set_input_source(Inbox {
+                    node,
+                    receiver: receivers[node].take().unwrap(),
+                    monitor: Arc::clone(&monitor)
+                });
+            }
+            let targets: Vec<(usize, Sender<i64>)> = self.links.iter()
+                .filter(|(from, _)| *from == node)
+                .map(|(_, to)| (*to, senders[*to].clone()))
+                .collect();
+            if !targets.is_empty() {
+                computer.set_output_sink(Fanout { targets, monitor: Arc::clone(&monitor) });
+            }
+
+            let departure = Departure { node, monitor: Arc::clone(&monitor) };
+            handles.push(thread::spawn(move || run_node(name, computer, departure)));
+        }
+        // Only the nodes hold senders now, a channel closes once its upstream nodes are done
+        drop(senders);
+
+        // What the node did before panicking is lost with its thread
+        PipelineReport {
+            nodes: handles.into_iter().zip(names).map(|(handle, name)| {
+                handle.join().unwrap_or_else(|payload| NodeReport {
+                    name,
+                    outcome: NodeOutcome::Panicked(panic_message(payload)),
+                    outputs: vec![],
+                    inputs: 0,
+                    instructions: 0,
+                    elapsed: start.elapsed()
+                })
+            }).collect()
+        }
+    }
+}
+
+fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(message) => *message,
+        Err(payload) => payload.downcast_ref::<&str>().map(|message| message.to_string()).unwrap_or_default()
+    }
+}
+
+fn run_node(name: String, mut computer: Computer, departure: Departure) -> NodeReport {
+    let start = Instant::now();
+    let mut outputs = vec![];
+    let mut inputs = 0;
+    let mut instructions = 0;
+
+    let outcome = loop {
+        match computer.step() {
+            Ok(event) => {
+                if computer.state == State::AwaitingInput {
+                    break match departure.monitor.lock().unwrap().deadlocked {
+                        true => NodeOutcome::Deadlocked,
+                        false => NodeOutcome::Starved
+                    }
+                }
+                instructions += 1;
+                if event.input.is_some() {
+                    inputs += 1;
+                }
+                if let Some(value) = event.output {
+                    outputs.push(value);
+                }
+                if computer.state == State::Stopped {
+                    break NodeOutcome::Halted
+                }
+            },
+            Err(error) => break NodeOutcome::Failed(error)
+        }
+    };
+    // Closes the channels of the downstream nodes before they are told this node is done
+    drop(computer);
+    drop(departure);
+
+    NodeReport {
+        name,
+        outcome,
+        outputs,
+        inputs,
+        instructions,
+        elapsed: start.elapsed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::{Pipeline, PipelineReport, NodeOutcome};
+    use crate::computer::{Computer, IntcodeError};
+    use crate::device::DeviceFn;
+
+    const AMPLIFIER: [i64; 29] = [3,26,1001,26,-4,26,3,27,1002,27,2,27,1,27,26,
+                                  27,4,27,1001,28,-1,28,1005,28,6,99,0,0,5];
+
+    /// Amplifiers connected in a loop, the first one being given `start` after its setting
+    fn feedback_loop(settings: &[i64], start: Option<i64>) -> PipelineReport {
+        let mut pipeline = Pipeline::new();
+        for (index, setting) in settings.iter().enumerate() {
+            let mut computer = Computer::new(AMPLIFIER.to_vec());
+            computer.add_input(*setting);
+            if let (0, Some(value)) = (index, start) {
+                computer.add_input(value);
+            }
+            pipeline.add_node(&((b'A' + index as u8) as char).to_string(), computer);
+        }
+        for index in 0..settings.len() {
+            pipeline.connect(index, (index + 1) % settings.len());
+        }
+        pipeline.run()
+    }
+
+    #[test]
+    fn test_feedback_loop() {
+        let report = feedback_loop(&[9, 8, 7, 6, 5], Some(0));
+        assert_eq!(report.node("E").unwrap().outputs.last(), Some(&139629729));
+        assert!(report.nodes.iter().all(|node| node.outcome == NodeOutcome::Halted));
+        assert_eq!(report.node("A").unwrap().inputs, 6);
+        assert!(!report.deadlocked());
+
+        // Without the starting value, every amplifier waits for the previous one
+        let report = feedback_loop(&[9, 8, 7, 6, 5], None);
+        assert!(report.nodes.iter().all(|node| node.outcome == NodeOutcome::Deadlocked));
+        assert!(report.nodes.iter().all(|node| node.outputs.is_empty()));
+    }
+
+    #[test]
+    fn test_halt_propagates_downstream() {
+        // Doubles each input forever
+        let doubler = [3, 9, 1002, 9, 2, 9, 4, 9, 1105, 1, 0];
+        let mut pipeline = Pipeline::new();
+        let source = pipeline.add_node("source", Computer::new(vec![104, 1, 104, 2, 99]));
+        let first = pipeline.add_node("first", Computer::new(doubler.to_vec()));
+        let second = pipeline.add_node("second", Computer::new(doubler.to_vec()));
+        pipeline.connect(source, first);
+        pipeline.connect(first, second);
+
+        let report = pipeline.run();
+        assert_eq!(report.nodes[source].outcome, NodeOutcome::Halted);
+        assert_eq!(report.nodes[source].instructions, 3);
+        assert_eq!(report.nodes[first].outcome, NodeOutcome::Starved);
+        assert_eq!(report.nodes[second].outcome, NodeOutcome::Starved);
+        assert_eq!(report.nodes[second].outputs, vec![4, 8]);
+        assert_eq!(report.nodes[second].inputs, 2);
+        assert!(!report.deadlocked());
+    }
+
+    #[test]
+    fn test_fan_out_and_errors() {
+        let mut pipeline = Pipeline::new();
+        let source = pipeline.add_node("source", Computer::new(vec![104, 42, 99]));
+        let echo = pipeline.add_node("echo", Computer::new(vec![3, 5, 4, 5, 99, 0]));
+        // Stores its input over its next instruction
+        let broken = pipeline.add_node("broken", Computer::new(vec![3, 2, 0]));
+        pipeline.connect(source, echo);
+        pipeline.connect(source, broken);
+
+        let report = pipeline.run();
+        assert_eq!(report.nodes[echo].outputs, vec![42]);
+        assert_eq!(report.nodes[broken].outcome, NodeOutcome::Failed(IntcodeError::UnknownOpCode {
+            instruction_pointer: 2,
+            opcode: 42
+        }));
+    }
+
+    #[test]
+    fn test_panicking_node() {
+        let mut pipeline = Pipeline::new();
+        let source = pipeline.add_node("source", Computer::new(vec![104, 42, 99]));
+        // Reads a device which panics
+        let mut computer = Computer::new(vec![3, 9, 4, 100, 99]);
+        computer.attach_device(100..101, DeviceFn { read: |_| panic!("sensor unplugged"), write: |_, _| {} });
+        let faulty = pipeline.add_node("faulty", computer);
+        let sink = pipeline.add_node("sink", Computer::new(vec![3, 5, 4, 5, 99, 0]));
+        pipeline.connect(source, faulty);
+        pipeline.connect(faulty, sink);
+
+        let report = pipeline.run();
+        assert_eq!(report.nodes[source].outcome, NodeOutcome::Halted);
+        assert_eq!(report.nodes[faulty].name, "faulty");
+        assert_eq!(report.nodes[faulty].outcome, NodeOutcome::Panicked("sensor unplugged".to_string()));
+        assert_eq!(report.nodes[sink].outcome, NodeOutcome::Starved);
+    }
+}
@@ -0,0 +1,235 @@
+//!
+//! Execution profile of a computer, see `Computer::enable_profile`
+//!
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Write;
+use crate::computer::{OpCode, Mode, StepEvent, parameter_cell, parameter_address};
+use crate::memory::Memory;
+use crate::registry::{Access, InstructionSet};
+
+/// Cells the instruction at `instruction_pointer` is about to read its operands from
+pub(crate) fn operand_reads(memory: &dyn Memory, instructions: &InstructionSet, instruction_pointer: i64, base: i64) -> Vec<i64> {
+    let (parameter, opcode) = match memory.get(instruction_pointer).map(|raw| instructions.decode(raw, instruction_pointer)) {
+        Some(Ok(decoded)) => decoded,
+        _ => return vec![]
+    };
+    let accesses = match instructions.definition(opcode.code()) {
+        Some(definition) => &definition.parameters,
+        None => return vec![]
+    };
+    parameter.modes().iter().zip(accesses).enumerate()
+        .filter(|(_, (_, access))| **access == Access::Read)
+        .filter_map(|(position, (mode, _))| {
+            let value = memory.get(parameter_cell(instruction_pointer, position + 1)?)?;
+            match mode {
+                Mode::Immediate => None,
+                // wraps around like the computer does without overflow check
+                _ => Some(parameter_address(*mode, value, base).0)
+            }
+        })
+        .collect()
+}
+
+/// Entries sorted by decreasing count, the key breaking ties
+fn hottest<K: Ord + Clone>(counts: &HashMap<K, u64>, top: usize) -> Vec<(K, u64)> {
+    let mut entries: Vec<(K, u64)> = counts.iter().map(|(key, count)| (key.clone(), *count)).collect();
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(top);
+    entries
+}
+
+/// Address some jumps lead to
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct JumpTarget {
+    pub address: i64,
+    /// Jumps taken to this address
+    pub count: u64,
+    /// Addresses of the jump instructions and how many times each one jumped here, hottest first
+    pub sources: Vec<(i64, u64)>
+}
+
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Profile {
+    /// Instructions executed
+    pub instructions: u64,
+    pub opcodes: HashMap<OpCode, u64>,
+    /// Names of the opcodes executed, from the instruction set of the computer
+    pub mnemonics: HashMap<OpCode, &'static str>,
+    /// Executions by instruction address
+    pub addresses: HashMap<i64, u64>,
+    /// Executions by opcode and modes of its parameters
+    pub modes: HashMap<(OpCode, Vec<Mode>), u64>,
+    /// Operand reads by cell, the instructions themselves are not counted
+    pub reads: HashMap<i64, u64>,
+    pub writes: HashMap<i64, u64>,
+    /// Jumps taken, by (instruction address, target)
+    pub jumps: HashMap<(i64, i64), u64>,
+    /// Highest relative base reached
+    pub deepest_base: i64
+}
+
+impl Profile {
+    pub(crate) fn record(&mut self, event: &StepEvent, reads: &[i64], base: i64, instructions: &InstructionSet) {
+        self.instructions += 1;
+        *self.opcodes.entry(event.opcode).or_insert(0) += 1;
+        self.mnemonics.entry(event.opcode).or_insert_with(|| instructions.mnemonic(event.opcode));
+        *self.addresses.entry(event.instruction_pointer).or_insert(0) += 1;
+        if let Ok((parameter, opcode)) = instructions.decode(event.raw_opcode, event.instruction_pointer) {
+            let modes = parameter.modes()[..instructions.parameter_count(opcode)].to_vec();
+            *self.modes.entry((opcode, modes)).or_insert(0) += 1;
+        }
+        for address in reads {
+            *self.reads.entry(*address).or_insert(0) += 1;
+        }
+        for write in event.writes.iter() {
+            *self.writes.entry(write.address).or_insert(0) += 1;
+        }
+        if let Some(target) = event.jump {
+            *self.jumps.entry((event.instruction_pointer, target)).or_insert(0) += 1;
+        }
+        self.deepest_base = self.deepest_base.max(base);
+    }
+
+    /// Adds the counts of another run, of the same program or not
+    pub fn merge(&mut self, other: &Profile) {
+        fn add<K: Clone + Eq + std::hash::Hash>(counts: &mut HashMap<K, u64>, other: &HashMap<K, u64>) {
+            for (key, count) in other.iter() {
+                *counts.entry(key.clone()).or_insert(0) += count;
+            }
+        }
+        self.instructions += other.instructions;
+        add(&mut self.opcodes, &other.opcodes);
+        for (opcode, mnemonic) in other.mnemonics.iter() {
+            self.mnemonics.entry(*opcode).or_insert(mnemonic);
+        }
+        add(&mut self.addresses, &other.addresses);
+        add(&mut self.modes, &other.modes);
+        add(&mut self.reads, &other.reads);
+        add(&mut self.writes, &other.writes);
+        add(&mut self.jumps, &other.jumps);
+        self.deepest_base = self.deepest_base.max(other.deepest_base);
+    }
+
+    /// Jump targets by decreasing number of jumps
+    pub fn jump_targets(&self) -> Vec<JumpTarget> {
+        let mut sources: HashMap<i64, HashMap<i64, u64>> = HashMap::new();
+        for ((from, to), count) in self.jumps.iter() {
+            sources.entry(*to).or_default().insert(*from, *count);
+        }
+        let mut targets: Vec<JumpTarget> = sources.into_iter()
+            .map(|(address, sources)| JumpTarget {
+                address,
+                count: sources.values().sum(),
+                sources: hottest(&sources, sources.len())
+            })
+            .collect();
+        targets.sort_by_key(|target| (Reverse(target.count), target.address));
+        targets
+    }
+
+    ///
+    /// Text report of the `top` hottest entries of each kind. `describe` gives the text shown
+    /// next to an instruction address, for instance its disassembly.
+    ///
+    pub fn report<F: Fn(i64) -> String>(&self, top: usize, describe: F) -> String {
+        let mut report = String::new();
+        let percent = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+        let mnemonic = |opcode: &OpCode| self.mnemonics.get(opcode).copied().unwrap_or("?");
+
+        writeln!(report, "{} instructions, deepest relative base {}", self.instructions, self.deepest_base).unwrap();
+
+        writeln!(report, "\nopcodes:").unwrap();
+        let mut opcodes: Vec<(OpCode, u64)> = self.opcodes.iter().map(|(opcode, count)| (*opcode, *count)).collect();
+        opcodes.sort_by_key(|(opcode, count)| (Reverse(*count), opcode.code()));
+        for (opcode, count) in opcodes.iter().take(top) {
+            writeln!(report, "{:>12} {:>6.2}%  {}", count, percent(*count), mnemonic(opcode)).unwrap();
+        }
+
+        writeln!(report, "\nmodes:").unwrap();
+        let mut modes: Vec<(&(OpCode, Vec<Mode>), &u64)> = self.modes.iter().collect();
+        modes.sort_by_key(|((opcode, modes), count)| {
+            (Reverse(**count), opcode.code(), modes.iter().map(|mode| mode.code()).collect::<Vec<i64>>())
+        });
+        for ((opcode, parameter_modes), count) in modes.into_iter().take(top) {
+            let parameter_modes: Vec<&str> = parameter_modes.iter().map(|mode| match mode {
+                Mode::Positional => "position",
+                Mode::Immediate => "immediate",
+                Mode::Relative => "relative"
+            }).collect();
+            let instruction = format!("{} {}", mnemonic(opcode), parameter_modes.join(", "));
+            writeln!(report, "{:>12} {:>6.2}%  {}", count, percent(*count), instruction.trim_end()).unwrap();
+        }
+
+        writeln!(report, "\nhot instructions:").unwrap();
+        for (address, count) in hottest(&self.addresses, top) {
+            writeln!(report, "{:>12} {:>6.2}%  {}", count, percent(count), describe(address)).unwrap();
+        }
+
+        writeln!(report, "\nmost read cells:").unwrap();
+        for (address, count) in hottest(&self.reads, top) {
+            writeln!(report, "{:>12}  [{}]", count, address).unwrap();
+        }
+
+        writeln!(report, "\nmost written cells:").unwrap();
+        for (address, count) in hottest(&self.writes, top) {
+            writeln!(report, "{:>12}  [{}]", count, address).unwrap();
+        }
+
+        writeln!(report, "\njump targets:").unwrap();
+        for target in self.jump_targets().into_iter().take(top) {
+            writeln!(report, "{:>12}  {}", target.count, describe(target.address)).unwrap();
+            for (from, count) in target.sources.into_iter().take(top) {
+                writeln!(report, "{:>12}    <- {}", count, from).unwrap();
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::computer::{Computer, OpCode, Mode};
+    use crate::profile::JumpTarget;
+
+    #[test]
+    fn test_profile_counts() {
+        // counts the input down to 0, then outputs it
+        let countdown = vec![3, 12, 1001, 12, -1, 12, 1005, 12, 2, 4, 12, 99, 0];
+        let mut computer = Computer::new(countdown);
+        computer.add_input(3);
+        assert!(computer.profile().is_none());
+        computer.enable_profile();
+        computer.run().unwrap();
+
+        let profile = computer.take_profile().unwrap();
+        assert_eq!(profile.instructions, 9);
+        assert_eq!(profile.opcodes.get(&OpCode::Add), Some(&3));
+        assert_eq!(profile.addresses.get(&6), Some(&3));
+        assert_eq!(profile.modes.get(&(OpCode::JumpIfTrue, vec![Mode::Positional, Mode::Immediate])), Some(&3));
+        // the add and the jump read the counter, then the output
+        assert_eq!(profile.reads.get(&12), Some(&7));
+        assert_eq!(profile.writes.get(&12), Some(&4));
+        assert_eq!(profile.jumps.get(&(6, 2)), Some(&2));
+        assert_eq!(profile.jump_targets(), vec![JumpTarget { address: 2, count: 2, sources: vec![(6, 2)] }]);
+        assert!(computer.profile().is_none());
+    }
+
+    #[test]
+    fn test_profile_base_and_report() {
+        let program = vec![109, 5, 109, -2, 204, 0, 99];
+        let mut computer = Computer::new(program);
+        computer.enable_profile();
+        computer.run().unwrap();
+
+        let profile = computer.profile().unwrap();
+        assert_eq!(profile.deepest_base, 5);
+        assert_eq!(profile.reads.get(&3), Some(&1));
+
+        let report = profile.report(2, |address| format!("@{}", address));
+        assert!(report.starts_with("4 instructions, deepest relative base 5\n"));
+        assert!(report.contains("2  50.00%  rb\n"));
+        assert!(report.contains("1  25.00%  out relative\n"));
+        assert!(report.contains("1  25.00%  @0\n"));
+    }
+}
@@ -0,0 +1,305 @@
+//!
+//! Instruction sets, the opcodes a computer understands.
+//!
+//! Every computer starts with the standard opcodes, executed by the interpreter itself. Variants of
+//! Intcode extend a copy of the standard set with their own opcodes, giving the parameters of the
+//! instruction and a handler computing its effect, then hand it to the computers running them:
+//!
+//! ```
+//! use std::sync::Arc;
+//! use common::computer::Computer;
+//! use common::registry::{InstructionSet, Access, Effect};
+//!
+//! // 10: [c] = a - b
+//! let mut instructions = InstructionSet::new();
+//! instructions.register(10, "sub", vec![Access::Read, Access::Read, Access::Write], |operands| Effect {
+//!     results: vec![operands[0] - operands[1]],
+//!     ..Effect::default()
+//! }).unwrap();
+//!
+//! let mut computer = Computer::new(vec![1110, 7, 2, 5, 4, 0, 99, 0]);
+//! computer.set_instruction_set(Arc::new(instructions));
+//! computer.run().unwrap();
+//! assert_eq!(computer.outputs(), &[5]);
+//! ```
+//!
+//! Once registered, the opcode is decoded, executed, assembled, disassembled and profiled
+//! like the standard ones by the users of the set. A computer without a set of its own shares
+//! the standard one, see `InstructionSet::standard`.
+//!
+use std::error::Error;
+use std::fmt;
+use std::sync::{Arc, OnceLock};
+use crate::computer::{IntcodeError, OpCode, Parameter};
+
+/// Opcodes are the two lowest digits of an instruction
+const OPCODE_LIMIT: i64 = 100;
+
+/// Only three mode digits fit in an instruction
+const MAX_PARAMETERS: usize = 3;
+
+/// What the instruction does with one of its parameters
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Access {
+    /// The value is an operand, resolved with the parameter mode
+    Read,
+    /// The value is the address of a result, it can't be immediate
+    Write
+}
+
+/// Outcome of a registered instruction
+#[derive(PartialEq, Eq, Debug, Clone, Default)]
+pub struct Effect {
+    /// Stored in order at the addresses of the `Write` parameters, the extra ones are ignored
+    pub results: Vec<i64>,
+    pub output: Option<i64>,
+    /// Next instruction, the one following this instruction if `None`
+    pub jump: Option<i64>
+}
+
+/// Computes the effect of an instruction from the values of its `Read` parameters
+pub type Handler = Arc<dyn Fn(&[i64]) -> Effect + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Definition {
+    pub opcode: OpCode,
+    pub mnemonic: &'static str,
+    pub parameters: Vec<Access>,
+    /// `None` for the standard opcodes, executed by the interpreter
+    pub handler: Option<Handler>
+}
+
+impl fmt::Debug for Definition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Definition")
+            .field("opcode", &self.opcode)
+            .field("mnemonic", &self.mnemonic)
+            .field("parameters", &self.parameters)
+            .finish()
+    }
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum RegistryError {
+    /// Not between 1 and 99
+    InvalidCode(i64),
+    CodeTaken(i64),
+    MnemonicTaken(String),
+    TooManyParameters(usize)
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RegistryError::InvalidCode(code) => write!(f, "opcode {} isn't between 1 and 99", code),
+            RegistryError::CodeTaken(code) => write!(f, "opcode {} is already registered", code),
+            RegistryError::MnemonicTaken(mnemonic) => write!(f, "mnemonic {} is already registered", mnemonic),
+            RegistryError::TooManyParameters(count) => {
+                write!(f, "{} parameters, an instruction has at most {}", count, MAX_PARAMETERS)
+            }
+        }
+    }
+}
+
+impl Error for RegistryError {}
+
+/// Opcodes understood by a computer, cloning it gives a set to extend independently
+#[derive(Debug, Clone)]
+pub struct InstructionSet {
+    /// Definitions indexed by opcode
+    definitions: Vec<Option<Definition>>
+}
+
+impl InstructionSet {
+    /// The standard opcodes, to be extended with `register`
+    pub fn new() -> InstructionSet {
+        use Access::{Read, Write};
+        let mut definitions = vec![None; OPCODE_LIMIT as usize];
+        let standard = [
+            (OpCode::Add, "add", vec![Read, Read, Write]),
+            (OpCode::Multiply, "mul", vec![Read, Read, Write]),
+            (OpCode::Store, "in", vec![Write]),
+            (OpCode::Output, "out", vec![Read]),
+            (OpCode::JumpIfTrue, "jt", vec![Read, Read]),
+            (OpCode::JumpIfFalse, "jf", vec![Read, Read]),
+            (OpCode::LessThan, "lt", vec![Read, Read, Write]),
+            (OpCode::Equals, "eq", vec![Read, Read, Write]),
+            (OpCode::Base, "rb", vec![Read]),
+            (OpCode::Stop, "hlt", vec![])
+        ];
+        for (opcode, mnemonic, parameters) in standard {
+            definitions[opcode.code() as usize] = Some(Definition { opcode, mnemonic, parameters, handler: None });
+        }
+        InstructionSet { definitions }
+    }
+
+    /// The standard opcodes, shared by every computer not given a set of its own
+    pub fn standard() -> Arc<InstructionSet> {
+        Arc::clone(standard())
+    }
+
+    pub fn definition(&self, code: i64) -> Option<&Definition> {
+        if !(0..OPCODE_LIMIT).contains(&code) {
+            return None
+        }
+        self.definitions[code as usize].as_ref()
+    }
+
+    pub fn definition_by_mnemonic(&self, mnemonic: &str) -> Option<&Definition> {
+        self.definitions.iter().flatten().find(|definition| definition.mnemonic == mnemonic)
+    }
+
+    /// Number of parameters following the instruction, 0 if the opcode isn't in the set
+    pub fn parameter_count(&self, opcode: OpCode) -> usize {
+        self.definition(opcode.code()).map_or(0, |definition| definition.parameters.len())
+    }
+
+    /// Short name used by the assembler and the disassembler, `?` if the opcode isn't in the set
+    pub fn mnemonic(&self, opcode: OpCode) -> &'static str {
+        self.definition(opcode.code()).map_or("?", |definition| definition.mnemonic)
+    }
+
+    /// Splits an instruction into its parameter modes and one of the opcodes of the set
+    pub fn decode(&self, instruction: i64, instruction_pointer: i64) -> Result<(Parameter, OpCode), IntcodeError> {
+        let opcode = match self.definition(instruction % 100) {
+            Some(definition) => definition.opcode,
+            None => return Err(IntcodeError::UnknownOpCode { instruction_pointer, opcode: instruction })
+        };
+        Ok((Parameter::decode(instruction, instruction_pointer)?, opcode))
+    }
+
+    ///
+    /// Adds an opcode to the set, returned as `OpCode::Custom`. `handler` gets the values
+    /// of the `Read` parameters in order.
+    ///
+    pub fn register<F>(&mut self, code: i64, mnemonic: &'static str, parameters: Vec<Access>, handler: F) -> Result<OpCode, RegistryError>
+        where F: Fn(&[i64]) -> Effect + Send + Sync + 'static {
+
+        if !(1..OPCODE_LIMIT).contains(&code) {
+            return Err(RegistryError::InvalidCode(code))
+        }
+        if parameters.len() > MAX_PARAMETERS {
+            return Err(RegistryError::TooManyParameters(parameters.len()))
+        }
+        if self.definitions[code as usize].is_some() {
+            return Err(RegistryError::CodeTaken(code))
+        }
+        if self.definition_by_mnemonic(mnemonic).is_some() {
+            return Err(RegistryError::MnemonicTaken(mnemonic.to_string()))
+        }
+
+        let opcode = OpCode::Custom(code);
+        self.definitions[code as usize] = Some(Definition { opcode, mnemonic, parameters, handler: Some(Arc::new(handler)) });
+        Ok(opcode)
+    }
+}
+
+impl Default for InstructionSet {
+    fn default() -> InstructionSet {
+        InstructionSet::new()
+    }
+}
+
+/// The set of the standard opcodes, built once
+pub(crate) fn standard() -> &'static Arc<InstructionSet> {
+    static STANDARD: OnceLock<Arc<InstructionSet>> = OnceLock::new();
+    STANDARD.get_or_init(|| Arc::new(InstructionSet::new()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::registry::{InstructionSet, Access, Effect, RegistryError};
+    use crate::computer::{Computer, OpCode, IntcodeError, State};
+    use crate::asm::assemble_with;
+    use crate::disasm::disassemble_with;
+    use crate::coverage::Coverage;
+    use crate::trace::replay_with;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_standard_opcodes() {
+        let mut instructions = InstructionSet::new();
+        let add = instructions.definition(1).unwrap();
+        assert_eq!(add.opcode, OpCode::Add);
+        assert_eq!(add.parameters, vec![Access::Read, Access::Read, Access::Write]);
+        assert!(add.handler.is_none());
+        assert_eq!(instructions.definition_by_mnemonic("hlt").unwrap().opcode, OpCode::Stop);
+        assert!(instructions.definition(0).is_none());
+        assert!(instructions.definition(100).is_none());
+
+        assert_eq!(instructions.register(2, "times", vec![], |_| Effect::default()).unwrap_err(), RegistryError::CodeTaken(2));
+        assert_eq!(instructions.register(100, "big", vec![], |_| Effect::default()).unwrap_err(), RegistryError::InvalidCode(100));
+        assert_eq!(instructions.register(60, "out", vec![], |_| Effect::default()).unwrap_err(), RegistryError::MnemonicTaken("out".to_string()));
+        assert_eq!(instructions.register(60, "many", vec![Access::Read; 4], |_| Effect::default()).unwrap_err(), RegistryError::TooManyParameters(4));
+    }
+
+    #[test]
+    fn test_custom_opcodes() {
+        // 61: [c] = a - b, 62: outputs a / b and jumps to c if the division has a remainder
+        let mut instructions = InstructionSet::new();
+        let sub = instructions.register(61, "sub", vec![Access::Read, Access::Read, Access::Write], |operands| Effect {
+            results: vec![operands[0] - operands[1]],
+            ..Effect::default()
+        }).unwrap();
+        let div = instructions.register(62, "divjr", vec![Access::Read, Access::Read, Access::Read], |operands| Effect {
+            output: Some(operands[0] / operands[1]),
+            jump: Some(operands[2]).filter(|_| operands[0] % operands[1] != 0),
+            ..Effect::default()
+        }).unwrap();
+        assert_eq!(sub, OpCode::Custom(61));
+        assert_eq!(instructions.parameter_count(sub), 3);
+        assert_eq!(instructions.mnemonic(div), "divjr");
+        let instructions = Arc::new(instructions);
+
+        let program = assemble_with("
+                in [x]
+                sub [x], #2, [x]
+                divjr [x], #4, #odd
+                hlt
+        odd:    out #-1
+                hlt
+        x:      data 0", &instructions).unwrap();
+        assert_eq!(program[..6], [3, 14, 1061, 14, 2, 14]);
+        assert!(disassemble_with(&program, &instructions).iter().any(|line| line.to_string() == "    2: sub [14], #2, [14]"));
+
+        let mut computer = Computer::new(program.clone());
+        computer.set_instruction_set(Arc::clone(&instructions));
+        computer.enable_profile();
+        computer.enable_trace();
+        computer.add_input(11);
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[2, -1]);
+        assert_eq!(computer.peek(14), 9);
+
+        // profiled, covered and replayed like the standard opcodes
+        let profile = computer.take_profile().unwrap();
+        assert!(profile.report(5, |_| String::new()).contains("sub position, immediate, position"));
+        let coverage = Coverage::with_instruction_set(&program, &profile, &instructions);
+        assert_eq!(coverage.totals(), [5, 8, 1, 1]);
+        assert!(coverage.annotated_listing().contains("1 |     6: divjr [14], #4, #11"));
+        assert_eq!(replay_with(&computer.take_trace().unwrap(), Arc::clone(&instructions)), Ok(5));
+
+        let mut computer = Computer::new(program.clone());
+        computer.set_instruction_set(Arc::clone(&instructions));
+        computer.add_input(10);
+        let event = computer.step().and_then(|_| computer.step()).unwrap();
+        assert_eq!(event.opcode, sub);
+        assert_eq!(event.operands, vec![10, 2]);
+        // forks keep the set
+        let mut fork = computer.fork();
+        assert_eq!(computer.run().unwrap(), State::Stopped);
+        assert_eq!(computer.outputs(), &[2]);
+        assert_eq!(fork.run().unwrap(), State::Stopped);
+        assert_eq!(fork.outputs(), &[2]);
+
+        // the other computers only know the standard opcodes
+        let mut computer = Computer::new(program);
+        computer.add_input(11);
+        assert_eq!(computer.run(), Err(IntcodeError::UnknownOpCode { instruction_pointer: 2, opcode: 1061 }));
+        assert!(InstructionSet::standard().definition(61).is_none());
+
+        let mut computer = Computer::new(vec![63, 0, 0]);
+        computer.set_instruction_set(instructions);
+        assert_eq!(computer.run(), Err(IntcodeError::UnknownOpCode { instruction_pointer: 0, opcode: 63 }));
+    }
+}
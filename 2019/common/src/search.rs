@@ -0,0 +1,131 @@
+//!
+//! Breadth-first exploration of the states of a program, one input at a time
+//!
+use std::collections::VecDeque;
+use crate::computer::{Computer, IntcodeError, State, StateSet};
+use crate::memory::MemoryKind;
+
+/// What the search does with a state it reached
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum Visit {
+    /// Tries every input from this state
+    Expand,
+    /// Dead end
+    Prune,
+    /// This is the state looked for
+    Stop
+}
+
+/// Runs until the program waits for input or stops
+fn run_until_input(computer: &mut Computer) -> Result<State, IntcodeError> {
+    loop {
+        match computer.run()? {
+            State::Paused => (),
+            state => return Ok(state)
+        }
+    }
+}
+
+/// Loads the program in a copy-on-write memory, as forks are cheap with it. The states visited
+/// are kept to tell them apart, they share their pages with the copy-on-write memory too.
+pub fn search_root(program: Vec<i64>) -> Computer {
+    Computer::with_memory(program, MemoryKind::CopyOnWrite.create())
+}
+
+///
+/// Runs `root` until it waits for input, then gives it each of `choices` on a fork of its own,
+/// level by level. `visit` gets the inputs leading to each new state and the machine in that state,
+/// its outputs being the ones of the last input only. A state the search already went through is
+/// not visited again, nor is an input making the program fail.
+///
+/// Returns the shortest inputs leading to a state `visit` stops on, with the machine in that state.
+///
+pub fn breadth_first_search<F>(mut root: Computer, choices: &[i64], mut visit: F) -> Result<Option<(Vec<i64>, Computer)>, IntcodeError>
+    where F: FnMut(&[i64], &mut Computer) -> Visit {
+
+    if run_until_input(&mut root)? != State::AwaitingInput {
+        return Ok(None)
+    }
+
+    let mut seen = StateSet::default();
+    seen.insert(&root);
+    let mut queue = VecDeque::new();
+    queue.push_back((vec![], root));
+
+    while let Some((inputs, computer)) = queue.pop_front() {
+        for choice in choices {
+            let mut child = computer.fork();
+            child.drain_outputs();
+            child.add_input(*choice);
+            let state = match run_until_input(&mut child) {
+                Ok(state) => state,
+                Err(_) => continue
+            };
+            if !seen.insert(&child) {
+                continue;
+            }
+
+            let mut path = inputs.clone();
+            path.push(*choice);
+            match visit(&path, &mut child) {
+                Visit::Stop => return Ok(Some((path, child))),
+                Visit::Expand if state == State::AwaitingInput => queue.push_back((path, child)),
+                _ => ()
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search::{breadth_first_search, search_root, Visit};
+    use crate::asm::assemble;
+
+    /// Adds each input to a position and outputs it
+    const WALK: &str = "
+        loop:   in [step]
+                add [position], [step], [position]
+                add #0, #0, [step]
+                out [position]
+                jt #1, #loop
+        step:   data 0
+        position: data 0";
+
+    #[test]
+    fn test_shortest_inputs() {
+        let mut visits = 0;
+        let (inputs, computer) = breadth_first_search(search_root(assemble(WALK).unwrap()), &[2, 3], |_, computer| {
+            visits += 1;
+            match computer.outputs() {
+                [7] => Visit::Stop,
+                [position] if *position > 7 => Visit::Prune,
+                _ => Visit::Expand
+            }
+        }).unwrap().unwrap();
+
+        assert_eq!(inputs, vec![2, 2, 3]);
+        assert_eq!(computer.outputs(), &[7]);
+        // 2 then 3 lead to the same state as 3 then 2, the search goes there once
+        assert_eq!(visits, 6);
+    }
+
+    #[test]
+    fn test_exhausted_search() {
+        let found = breadth_first_search(search_root(assemble(WALK).unwrap()), &[2], |_, computer| {
+            match computer.outputs() {
+                [position] if *position > 10 => Visit::Prune,
+                _ => Visit::Expand
+            }
+        }).unwrap();
+        assert!(found.is_none());
+
+        // an input making the program fail is a dead end
+        let program = vec![3, 2, 0];
+        let found = breadth_first_search(search_root(program), &[42, 99], |inputs, _| {
+            assert_eq!(inputs, &[99]);
+            Visit::Stop
+        }).unwrap();
+        assert_eq!(found.unwrap().0, vec![99]);
+    }
+}
@@ -0,0 +1,211 @@
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write, BufReader, BufWriter, ErrorKind};
+use std::path::PathBuf;
+use crate::computer::{State, ResumeMode};
+use crate::memory::MemoryKind;
+use crate::encoding::{write_i64, read_i64, write_len, read_len, write_header, read_header};
+
+const MAGIC: &[u8; 4] = b"ICSN";
+const VERSION: u8 = 1;
+
+///
+/// The full state of a computer, see `Computer::snapshot` and `Computer::restore`
+///
+/// File layout (version 1), integers as zigzag varints:
+/// `ICSN`, version byte, memory kind, memory cells count then (address, value) pairs, inputs count
+/// then values, outputs count then values, instruction pointer, base, state, resume mode.
+///
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Snapshot {
+    /// Backend the memory is restored in
+    pub memory_kind: MemoryKind,
+    /// Memory cells sorted by address
+    pub memory: Vec<(i64, i64)>,
+    /// Inputs still waiting in the queue
+    pub inputs: Vec<i64>,
+    pub outputs: Vec<i64>,
+    pub instruction_pointer: i64,
+    pub base: i64,
+    pub state: State,
+    pub resume_mode: ResumeMode
+}
+
+fn state_to_code(state: State) -> i64 {
+    match state {
+        State::Started => 0,
+        State::Paused => 1,
+        State::AwaitingInput => 2,
+        State::Stopped => 3,
+        State::Interrupted => 4
+    }
+}
+
+fn state_from_code(code: i64) -> io::Result<State> {
+    match code {
+        0 => Ok(State::Started),
+        1 => Ok(State::Paused),
+        2 => Ok(State::AwaitingInput),
+        3 => Ok(State::Stopped),
+        4 => Ok(State::Interrupted),
+        _ => Err(io::Error::new(ErrorKind::InvalidData, format!("unknown state {}", code)))
+    }
+}
+
+fn kind_to_code(kind: MemoryKind) -> i64 {
+    match kind {
+        MemoryKind::Dense => 0,
+        MemoryKind::Sparse => 1,
+        MemoryKind::CopyOnWrite => 2
+    }
+}
+
+fn kind_from_code(code: i64) -> io::Result<MemoryKind> {
+    match code {
+        0 => Ok(MemoryKind::Dense),
+        1 => Ok(MemoryKind::Sparse),
+        2 => Ok(MemoryKind::CopyOnWrite),
+        _ => Err(io::Error::new(ErrorKind::InvalidData, format!("unknown memory kind {}", code)))
+    }
+}
+
+fn write_values<W: Write>(writer: &mut W, values: &[i64]) -> io::Result<()> {
+    write_len(writer, values.len())?;
+    for value in values {
+        write_i64(writer, *value)?;
+    }
+    Ok(())
+}
+
+fn read_values<R: Read>(reader: &mut R) -> io::Result<Vec<i64>> {
+    let len = read_len(reader)?;
+    (0..len).map(|_| read_i64(reader)).collect()
+}
+
+impl Snapshot {
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_header(writer, MAGIC, VERSION)?;
+        write_i64(writer, kind_to_code(self.memory_kind))?;
+        write_len(writer, self.memory.len())?;
+        for (address, value) in self.memory.iter() {
+            write_i64(writer, *address)?;
+            write_i64(writer, *value)?;
+        }
+        write_values(writer, &self.inputs)?;
+        write_values(writer, &self.outputs)?;
+        write_i64(writer, self.instruction_pointer)?;
+        write_i64(writer, self.base)?;
+        write_i64(writer, state_to_code(self.state))?;
+        write_i64(writer, match self.resume_mode {
+            ResumeMode::Disable => 0,
+            ResumeMode::Enable => 1
+        })
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Snapshot> {
+        let version = read_header(reader, MAGIC)?;
+        if version != VERSION {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("unsupported snapshot version {}", version)))
+        }
+        let memory_kind = kind_from_code(read_i64(reader)?)?;
+
+        let mut memory = vec![];
+        for _ in 0..read_len(reader)? {
+            memory.push((read_i64(reader)?, read_i64(reader)?));
+        }
+        let inputs = read_values(reader)?;
+        let outputs = read_values(reader)?;
+        let instruction_pointer = read_i64(reader)?;
+        let base = read_i64(reader)?;
+        let state = state_from_code(read_i64(reader)?)?;
+        let resume_mode = match read_i64(reader)? {
+            0 => ResumeMode::Disable,
+            1 => ResumeMode::Enable,
+            code => return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown resume mode {}", code)))
+        };
+
+        Ok(Snapshot { memory_kind, memory, inputs, outputs, instruction_pointer, base, state, resume_mode })
+    }
+
+    pub fn save(&self, path: PathBuf) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: PathBuf) -> Result<Snapshot, Box<dyn Error>> {
+        let mut reader = BufReader::new(File::open(&path)?);
+        Ok(Snapshot::read_from(&mut reader)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Snapshot;
+    use crate::computer::{Computer, ResumeMode, State};
+    use crate::memory::MemoryKind;
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let program = vec![3,9,4,9,3,10,4,10,99,-1,-1];
+        let mut computer = Computer::new(program);
+        computer.set_resume_mode(ResumeMode::Enable);
+        computer.add_input(12);
+        computer.run().unwrap();
+
+        let snapshot = computer.snapshot();
+        assert_eq!(snapshot.instruction_pointer, 4);
+        assert_eq!(snapshot.outputs, vec![12]);
+        assert_eq!(snapshot.state, State::Paused);
+
+        // two forks from the same point
+        let mut first = Computer::restore(&snapshot);
+        first.add_input(1);
+        first.run().unwrap();
+        assert_eq!(first.outputs(), &[12, 1]);
+
+        let mut second = Computer::restore(&snapshot);
+        second.add_input(2);
+        second.run().unwrap();
+        assert_eq!(second.outputs(), &[12, 2]);
+        assert_eq!(Computer::restore(&snapshot).snapshot(), snapshot);
+    }
+
+    #[test]
+    fn test_snapshot_file_round_trip() {
+        let mut computer = Computer::new(vec![3, 100000000000000, 4, 100000000000000, 99]);
+        computer.add_input(-42);
+        computer.add_input(7);
+        computer.step().unwrap();
+        let snapshot = computer.snapshot();
+        assert_eq!(snapshot.inputs, vec![7]);
+
+        let mut buffer = vec![];
+        snapshot.write_to(&mut buffer).unwrap();
+        assert_eq!(&buffer[..6], b"ICSN\x01\x00");
+        assert_eq!(Snapshot::read_from(&mut &buffer[..]).unwrap(), snapshot);
+
+        let mut restored = Computer::restore(&Snapshot::read_from(&mut &buffer[..]).unwrap());
+        restored.run().unwrap();
+        assert_eq!(restored.memory().get(100000000000000), Some(-42));
+        assert_eq!(restored.outputs(), &[-42]);
+    }
+
+    #[test]
+    fn test_snapshot_rejects_unknown_versions() {
+        let mut buffer = vec![];
+        Computer::new(vec![99]).snapshot().write_to(&mut buffer).unwrap();
+        buffer[4] = 2;
+        assert!(Snapshot::read_from(&mut &buffer[..]).is_err());
+    }
+
+    #[test]
+    fn test_restore_keeps_the_memory_kind() {
+        let mut computer = Computer::with_memory(vec![3, 5, 99], MemoryKind::CopyOnWrite.create());
+        computer.add_input(4);
+        let snapshot = computer.snapshot();
+        assert_eq!(snapshot.memory_kind, MemoryKind::CopyOnWrite);
+        assert_eq!(Computer::restore(&snapshot).memory().kind(), MemoryKind::CopyOnWrite);
+    }
+}
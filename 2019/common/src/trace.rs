@@ -0,0 +1,450 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write, BufReader, BufWriter, ErrorKind};
+use std::path::PathBuf;
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use crate::computer::{Computer, DeviceAccess, MemoryWrite, OpCode, State, StepEvent, IntcodeError};
+use crate::device::DeviceFn;
+use crate::registry::InstructionSet;
+use crate::encoding::{write_i64, read_i64, write_len, read_len, write_string, read_string, write_header, read_header};
+
+const MAGIC: &[u8; 4] = b"ICTR";
+const VERSION: u8 = 1;
+
+/// What an executed instruction changed in the machine
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct TraceEntry {
+    pub instruction_pointer: i64,
+    pub raw_opcode: i64,
+    pub writes: Vec<MemoryWrite>,
+    /// Reads and writes of the devices, the reads are given back to the replay
+    pub device_accesses: Vec<DeviceAccess>,
+    pub input: Option<i64>,
+    pub output: Option<i64>
+}
+
+impl From<&StepEvent> for TraceEntry {
+    fn from(event: &StepEvent) -> TraceEntry {
+        TraceEntry {
+            instruction_pointer: event.instruction_pointer,
+            raw_opcode: event.raw_opcode,
+            writes: event.writes.clone(),
+            device_accesses: event.device_accesses.clone(),
+            input: event.input,
+            output: event.output
+        }
+    }
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>5}: {}", self.instruction_pointer, self.raw_opcode)?;
+        for write in self.writes.iter() {
+            write!(f, " [{}]: {} -> {}", write.address, write.old, write.new)?;
+        }
+        for access in self.device_accesses.iter() {
+            match access {
+                DeviceAccess::Read { address, value } => write!(f, " device [{}]: read {}", address, value)?,
+                DeviceAccess::Write { address, value } => write!(f, " device [{}]: write {}", address, value)?
+            }
+        }
+        if let Some(input) = self.input {
+            write!(f, " in {}", input)?;
+        }
+        if let Some(output) = self.output {
+            write!(f, " out {}", output)?;
+        }
+        Ok(())
+    }
+}
+
+///
+/// Every instruction executed by a computer since the tracing was enabled, along with the state
+/// of the machine at that time so the run can be replayed. The instructions undone with
+/// `Computer::step_back` are dropped from the trace.
+///
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub struct Trace {
+    /// Non-empty memory cells sorted by address
+    pub memory: Vec<(i64, i64)>,
+    pub instruction_pointer: i64,
+    pub base: i64,
+    /// Opcodes outside of the standard set executed during the run, with their mnemonic, by code
+    pub custom_opcodes: Vec<(i64, String)>,
+    pub entries: Vec<TraceEntry>
+}
+
+impl Trace {
+    pub(crate) fn new(mut memory: Vec<(i64, i64)>, instruction_pointer: i64, base: i64) -> Trace {
+        memory.sort();
+        Trace { memory, instruction_pointer, base, custom_opcodes: vec![], entries: vec![] }
+    }
+
+    pub(crate) fn record(&mut self, event: &StepEvent, instructions: &InstructionSet) {
+        if let OpCode::Custom(code) = event.opcode {
+            if !self.custom_opcodes.iter().any(|(known, _)| *known == code) {
+                self.custom_opcodes.push((code, instructions.mnemonic(event.opcode).to_string()));
+                self.custom_opcodes.sort();
+            }
+        }
+        self.entries.push(TraceEntry::from(event));
+    }
+
+    /// Inputs consumed during the run, in order
+    pub fn inputs(&self) -> Vec<i64> {
+        self.entries.iter().filter_map(|x| x.input).collect()
+    }
+
+    pub fn outputs(&self) -> Vec<i64> {
+        self.entries.iter().filter_map(|x| x.output).collect()
+    }
+
+    ///
+    /// A computer in the state where the trace started, its inputs already queued. The devices
+    /// accessed during the run are replaced by ones giving back the values read then.
+    ///
+    pub fn to_computer(&self) -> Computer {
+        self.to_computer_with(InstructionSet::standard())
+    }
+
+    /// A computer running the opcodes of the set in the state where the trace started, see `to_computer`
+    pub fn to_computer_with(&self, instructions: Arc<InstructionSet>) -> Computer {
+        let mut computer = Computer::new(vec![]);
+        computer.set_instruction_set(instructions);
+        for (address, value) in self.memory.iter() {
+            computer.write(*address, *value);
+        }
+        computer.set_instruction_pointer(self.instruction_pointer);
+        computer.set_base(self.base);
+        for input in self.inputs() {
+            computer.add_input(input);
+        }
+
+        let mut reads: HashMap<i64, VecDeque<i64>> = HashMap::new();
+        for access in self.entries.iter().flat_map(|entry| entry.device_accesses.iter()) {
+            match access {
+                DeviceAccess::Read { address, value } => reads.entry(*address).or_default().push_back(*value),
+                DeviceAccess::Write { address, .. } => {
+                    reads.entry(*address).or_default();
+                }
+            }
+        }
+        for (address, mut values) in reads {
+            computer.attach_device(address..address.saturating_add(1), DeviceFn {
+                read: move |_| values.pop_front().unwrap_or(0),
+                write: |_, _| ()
+            });
+        }
+        computer
+    }
+
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        write_header(writer, MAGIC, VERSION)?;
+        write_len(writer, self.memory.len())?;
+        for (address, value) in self.memory.iter() {
+            write_i64(writer, *address)?;
+            write_i64(writer, *value)?;
+        }
+        write_i64(writer, self.instruction_pointer)?;
+        write_i64(writer, self.base)?;
+        write_len(writer, self.custom_opcodes.len())?;
+        for (code, mnemonic) in self.custom_opcodes.iter() {
+            write_i64(writer, *code)?;
+            write_string(writer, mnemonic)?;
+        }
+
+        write_len(writer, self.entries.len())?;
+        for entry in self.entries.iter() {
+            // bit 0: an input follows, bit 1: an output follows
+            let flags = entry.input.is_some() as i64 | (entry.output.is_some() as i64) << 1;
+            write_i64(writer, flags)?;
+            write_i64(writer, entry.instruction_pointer)?;
+            write_i64(writer, entry.raw_opcode)?;
+            write_len(writer, entry.writes.len())?;
+            for write in entry.writes.iter() {
+                write_i64(writer, write.address)?;
+                write_i64(writer, write.old)?;
+                write_i64(writer, write.new)?;
+            }
+            write_len(writer, entry.device_accesses.len())?;
+            for access in entry.device_accesses.iter() {
+                // 0: read, 1: write
+                let (kind, address, value) = match access {
+                    DeviceAccess::Read { address, value } => (0, address, value),
+                    DeviceAccess::Write { address, value } => (1, address, value)
+                };
+                write_i64(writer, kind)?;
+                write_i64(writer, *address)?;
+                write_i64(writer, *value)?;
+            }
+            if let Some(input) = entry.input {
+                write_i64(writer, input)?;
+            }
+            if let Some(output) = entry.output {
+                write_i64(writer, output)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_from<R: Read>(reader: &mut R) -> io::Result<Trace> {
+        let version = read_header(reader, MAGIC)?;
+        if version != VERSION {
+            return Err(io::Error::new(ErrorKind::InvalidData, format!("unsupported trace version {}", version)))
+        }
+
+        let mut memory = vec![];
+        for _ in 0..read_len(reader)? {
+            memory.push((read_i64(reader)?, read_i64(reader)?));
+        }
+        let mut trace = Trace::new(memory, read_i64(reader)?, read_i64(reader)?);
+        for _ in 0..read_len(reader)? {
+            trace.custom_opcodes.push((read_i64(reader)?, read_string(reader)?));
+        }
+
+        for _ in 0..read_len(reader)? {
+            let flags = read_i64(reader)?;
+            let instruction_pointer = read_i64(reader)?;
+            let raw_opcode = read_i64(reader)?;
+            let mut writes = vec![];
+            for _ in 0..read_len(reader)? {
+                writes.push(MemoryWrite { address: read_i64(reader)?, old: read_i64(reader)?, new: read_i64(reader)? });
+            }
+            let mut device_accesses = vec![];
+            for _ in 0..read_len(reader)? {
+                let kind = read_i64(reader)?;
+                let (address, value) = (read_i64(reader)?, read_i64(reader)?);
+                device_accesses.push(match kind {
+                    0 => DeviceAccess::Read { address, value },
+                    1 => DeviceAccess::Write { address, value },
+                    _ => return Err(io::Error::new(ErrorKind::InvalidData, format!("unknown device access {}", kind)))
+                });
+            }
+            let input = if flags & 1 != 0 { Some(read_i64(reader)?) } else { None };
+            let output = if flags & 2 != 0 { Some(read_i64(reader)?) } else { None };
+            trace.entries.push(TraceEntry { instruction_pointer, raw_opcode, writes, device_accesses, input, output });
+        }
+        Ok(trace)
+    }
+
+    pub fn save(&self, path: PathBuf) -> Result<(), Box<dyn Error>> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        self.write_to(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: PathBuf) -> Result<Trace, Box<dyn Error>> {
+        let mut reader = BufReader::new(File::open(&path)?);
+        Ok(Trace::read_from(&mut reader)?)
+    }
+}
+
+/// First difference between a trace and its replay, `step` is the index of the trace entry
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum Divergence {
+    /// The trace ran an opcode the instruction set of the replay doesn't have under this mnemonic
+    UnknownOpCode { code: i64, mnemonic: String },
+    Mismatch { step: usize, expected: TraceEntry, found: TraceEntry },
+    Error { step: usize, expected: TraceEntry, error: IntcodeError },
+    /// The replay halted or waits for input while the trace goes on
+    Stalled { step: usize, expected: TraceEntry, state: State }
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Divergence::UnknownOpCode { code, mnemonic } => {
+                write!(f, "opcode {} ({}) of the trace isn't in the instruction set", code, mnemonic)
+            },
+            Divergence::Mismatch { step, expected, found } => {
+                write!(f, "step #{}\n expected {}\n    found {}", step, expected, found)
+            },
+            Divergence::Error { step, expected, error } => {
+                write!(f, "step #{}\n expected {}\n    error {}", step, expected, error)
+            },
+            Divergence::Stalled { step, expected, state } => {
+                write!(f, "step #{}\n expected {}\n    computer is {:?}", step, expected, state)
+            }
+        }
+    }
+}
+
+///
+/// Re-runs the traced program and checks every step against the trace,
+/// returns the number of steps replayed
+///
+pub fn replay(trace: &Trace) -> Result<usize, Box<Divergence>> {
+    replay_with(trace, InstructionSet::standard())
+}
+
+/// Replays a trace recorded by a computer running the opcodes of the set, see `replay`
+pub fn replay_with(trace: &Trace, instructions: Arc<InstructionSet>) -> Result<usize, Box<Divergence>> {
+    for (code, mnemonic) in trace.custom_opcodes.iter() {
+        if !matches!(instructions.definition(*code), Some(definition) if definition.mnemonic == mnemonic) {
+            return Err(Box::new(Divergence::UnknownOpCode { code: *code, mnemonic: mnemonic.clone() }))
+        }
+    }
+    let mut computer = trace.to_computer_with(instructions);
+    for (step, expected) in trace.entries.iter().enumerate() {
+        if computer.state == State::Stopped {
+            return Err(Box::new(Divergence::Stalled { step, expected: expected.clone(), state: computer.state }))
+        }
+        let event = match computer.step() {
+            Ok(event) => event,
+            Err(error) => return Err(Box::new(Divergence::Error { step, expected: expected.clone(), error }))
+        };
+        if computer.state == State::AwaitingInput {
+            return Err(Box::new(Divergence::Stalled { step, expected: expected.clone(), state: computer.state }))
+        }
+        let found = TraceEntry::from(&event);
+        if found != *expected {
+            return Err(Box::new(Divergence::Mismatch { step, expected: expected.clone(), found }))
+        }
+    }
+    Ok(trace.entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Trace, TraceEntry, Divergence, replay, replay_with};
+    use crate::computer::{Computer, DeviceAccess, MemoryWrite, State};
+    use crate::device::Random;
+    use crate::registry::{InstructionSet, Access, Effect};
+    use crate::asm::assemble;
+    use std::sync::Arc;
+
+    fn traced_run() -> Trace {
+        let mut computer = Computer::new(vec![3,9,8,9,10,9,4,9,99,-1,8]);
+        computer.enable_trace();
+        computer.add_input(8);
+        computer.run().unwrap();
+        computer.take_trace().unwrap()
+    }
+
+    #[test]
+    fn test_trace_records_execution() {
+        let trace = traced_run();
+        assert_eq!(trace.memory.len(), 11);
+        assert_eq!(trace.entries.len(), 4);
+        assert_eq!(trace.entries[0], TraceEntry {
+            instruction_pointer: 0, raw_opcode: 3, writes: vec![MemoryWrite { address: 9, old: -1, new: 8 }], device_accesses: vec![], input: Some(8), output: None
+        });
+        assert_eq!(trace.entries[1].writes, vec![MemoryWrite { address: 9, old: 8, new: 1 }]);
+        assert_eq!(trace.inputs(), vec![8]);
+        assert_eq!(trace.outputs(), vec![1]);
+    }
+
+    #[test]
+    fn test_trace_file_round_trip() {
+        let trace = traced_run();
+        let mut buffer = vec![];
+        trace.write_to(&mut buffer).unwrap();
+        assert_eq!(&buffer[..5], b"ICTR\x01");
+        assert_eq!(Trace::read_from(&mut &buffer[..]).unwrap(), trace);
+        assert!(Trace::read_from(&mut &buffer[..buffer.len() - 1]).is_err());
+        assert!(Trace::read_from(&mut &b"ELF\x01\x01"[..]).is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut trace = traced_run();
+        assert_eq!(replay(&trace), Ok(4));
+
+        // an interpreter that would have stored a wrong value
+        trace.entries[1].writes[0].new = 0;
+        match replay(&trace).map_err(|x| *x) {
+            Err(Divergence::Mismatch { step, found, .. }) => {
+                assert_eq!(step, 1);
+                assert_eq!(found.writes, vec![MemoryWrite { address: 9, old: 8, new: 1 }]);
+            },
+            other => panic!("Unexpected replay result {:?}", other)
+        }
+
+        let mut trace = traced_run();
+        let last = trace.entries.last().unwrap().clone();
+        trace.entries.push(last);
+        assert_eq!(replay(&trace), Err(Box::new(Divergence::Stalled { step: 4, expected: trace.entries[4].clone(), state: State::Stopped })));
+    }
+
+    fn round_trip(trace: &Trace) -> Trace {
+        let mut buffer = vec![];
+        trace.write_to(&mut buffer).unwrap();
+        Trace::read_from(&mut &buffer[..]).unwrap()
+    }
+
+    #[test]
+    fn test_replay_gives_back_the_device_reads() {
+        // outputs twice the value read from the random generator mapped at 1000
+        let program = assemble("
+                mul [1000], #2, [1001]
+                mul [1000], #2, [x]
+                out [x]
+                hlt
+        x:      data 0").unwrap();
+        let mut computer = Computer::new(program);
+        computer.attach_device(1000..1002, Random::new(7));
+        computer.enable_trace();
+        computer.run().unwrap();
+        let trace = round_trip(&computer.take_trace().unwrap());
+
+        let read = match trace.entries[0].device_accesses[0] {
+            DeviceAccess::Read { address: 1000, value } => value,
+            ref other => panic!("Unexpected device access {:?}", other)
+        };
+        assert_eq!(trace.entries[0].device_accesses[1], DeviceAccess::Write { address: 1001, value: read.wrapping_mul(2) });
+        assert!(trace.entries[0].to_string().contains(&format!(" device [1000]: read {}", read)));
+        assert_eq!(replay(&trace), Ok(4));
+        let mut replayed = trace.to_computer();
+        replayed.run().unwrap();
+        assert_eq!(replayed.outputs(), computer.outputs());
+    }
+
+    #[test]
+    fn test_replay_needs_the_custom_opcodes() {
+        let mut instructions = InstructionSet::new();
+        instructions.register(60, "neg", vec![Access::Read, Access::Write], |operands| Effect {
+            results: vec![-operands[0]],
+            ..Effect::default()
+        }).unwrap();
+        let instructions = Arc::new(instructions);
+        let mut computer = Computer::new(vec![160, 5, 6, 4, 6, 99, 0]);
+        computer.set_instruction_set(Arc::clone(&instructions));
+        computer.enable_trace();
+        computer.run().unwrap();
+        let trace = round_trip(&computer.take_trace().unwrap());
+        assert_eq!(trace.custom_opcodes, vec![(60, "neg".to_string())]);
+
+        let missing = Divergence::UnknownOpCode { code: 60, mnemonic: "neg".to_string() };
+        assert_eq!(missing.to_string(), "opcode 60 (neg) of the trace isn't in the instruction set");
+        assert_eq!(replay(&trace), Err(Box::new(missing)));
+        assert_eq!(replay_with(&trace, instructions), Ok(3));
+    }
+
+    #[test]
+    fn test_undone_steps_leave_the_trace() {
+        // counts the input down to 0, outputting each value
+        let countdown = vec![3, 13, 4, 13, 1001, 13, -1, 13, 1005, 13, 2, 99, 0, 0];
+        let mut computer = Computer::new(countdown);
+        computer.enable_history(None);
+        computer.add_input(2);
+        computer.step().unwrap();
+        computer.enable_trace();
+        computer.run().unwrap();
+        for _ in 0..3 {
+            computer.step_back().unwrap();
+        }
+        assert_eq!(computer.take_trace().unwrap().entries.len(), 4);
+
+        // going back before the start of the trace starts it again
+        computer.enable_trace();
+        computer.step_back().unwrap();
+        computer.run().unwrap();
+        let trace = computer.take_trace().unwrap();
+        assert_eq!(trace.instruction_pointer, 2);
+        assert_eq!(trace.outputs(), vec![1]);
+        assert_eq!(replay(&trace), Ok(4));
+    }
+}
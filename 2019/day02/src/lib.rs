@@ -1,15 +1,18 @@
 use std::path::PathBuf;
-use common::computer::{computer, read_program_file};
+use common::computer::{read_program_file, Computer, State};
+
+/// Far more instructions than a valid noun/verb pair needs
+const INSTRUCTION_BUDGET: u64 = 100_000;
 
 pub fn part_1(input_1: u32, input_2: u32) -> i64 {
     let path = PathBuf::from("./assets/program.txt");
-    let mut memory = read_program_file(path).unwrap();
+    let mut computer = Computer::new(read_program_file(path).unwrap());
     // Define inputs
-    memory[1] = input_1 as i64;
-    memory[2] = input_2 as i64;
+    computer.poke(1, input_1 as i64);
+    computer.poke(2, input_2 as i64);
     // Compute
-    let (memory, _) = computer(memory, None);
-    *memory.get(&0).unwrap()
+    computer.run().unwrap();
+    computer.peek(0)
 }
 
 pub fn part_2(result: u32) -> Option<(u32, u32)> {
@@ -19,12 +22,14 @@ pub fn part_2(result: u32) -> Option<(u32, u32)> {
 
     for i in 0..100 as u32 {
         for j in 0..100 as u32 {
-            let mut memory = memory_template.clone();
-            memory[1] = i as i64;
-            memory[2] = j as i64;
-            let (memory, _) = computer(memory, None);
-            if *memory.get(&0).unwrap() == result as i64{
-                return Some((i, j))
+            let mut computer = Computer::new(memory_template.clone());
+            computer.load_at(1, &[i as i64, j as i64]);
+            computer.set_instruction_budget(Some(INSTRUCTION_BUDGET));
+            // Some noun/verb pairs lead to an invalid or endless program, just try the next one
+            if let Ok(State::Stopped) = computer.run() {
+                if computer.peek(0) == result as i64 {
+                    return Some((i, j))
+                }
             }
         }
     }
@@ -6,7 +6,7 @@ pub fn part_1() -> Vec<i64> {
     let path = PathBuf::from("./assets/boost.txt");
     let memory = read_program_file(path).unwrap();
 
-    let (_, outputs) = computer(memory, Some(VecDeque::from(vec![1])));
+    let (_, outputs) = computer(memory, Some(VecDeque::from(vec![1]))).unwrap();
     outputs
 }
 
@@ -14,6 +14,6 @@ pub fn part_2() -> Vec<i64> {
     let path = PathBuf::from("./assets/boost.txt");
     let memory = read_program_file(path).unwrap();
 
-    let (_, outputs) = computer(memory, Some(VecDeque::from(vec![5])));
+    let (_, outputs) = computer(memory, Some(VecDeque::from(vec![5]))).unwrap();
     outputs
 }
\ No newline at end of file
@@ -30,11 +30,9 @@ impl Amplifier {
         }
         self.computer.add_input(input);
 
-        let (_, buffer) = self.computer.run();
-        match buffer.last() {
-            Some(x) => {
-                x.to_owned()
-            },
+        self.computer.run().unwrap();
+        match self.computer.last_output() {
+            Some(x) => x,
             None => panic!("Unable to get buffer value")
         }
     }
@@ -5,13 +5,13 @@ use std::collections::VecDeque;
 pub fn part_1() -> i64 {
     let path = PathBuf::from("./assets/boost.txt");
     let memory = read_program_file(path).unwrap();
-    let (_, buffer) = computer(memory, Some(VecDeque::from(vec![1])));
+    let (_, buffer) = computer(memory, Some(VecDeque::from(vec![1]))).unwrap();
     buffer.last().unwrap().to_owned()
 }
 
 pub fn part_2() -> i64 {
     let path = PathBuf::from("./assets/boost.txt");
     let memory = read_program_file(path).unwrap();
-    let (_, buffer) = computer(memory, Some(VecDeque::from(vec![2])));
+    let (_, buffer) = computer(memory, Some(VecDeque::from(vec![2]))).unwrap();
     buffer.last().unwrap().to_owned()
 }
\ No newline at end of file